@@ -0,0 +1,98 @@
+//! Measures how much CPU the server uses while lots of clients sit idle in a game.
+//!
+//! Start the server, then run:
+//!
+//! ```sh
+//! cargo run --release --example idle_load -- $(pgrep -x server-rs) [connections] [seconds] [host:port]
+//! ```
+//!
+//! A game is created, `connections` spectators join it, and the server's CPU time is sampled from
+//! `/proc/<server-pid>/stat` while nothing happens in the game. Linux only.
+
+use std::{env, fs, time::Duration};
+
+use futures::StreamExt;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::Instant,
+};
+
+const GAME: &str = r#"{"rounds":[[{"categoryId":"c1","name":"Idle","questions":[{"questionId":"q1","detail":"Question","correctAnswer":"Answer","value":100,"answered":false}]}]]}"#;
+
+/// Clock ticks per second used in `/proc`, which is 100 on practically every Linux system
+const USER_HZ: f64 = 100.0;
+
+#[tokio::main]
+async fn main() {
+    let mut args = env::args().skip(1);
+    let pid: u32 = args
+        .next()
+        .and_then(|x| x.parse().ok())
+        .expect("usage: idle_load <server-pid> [connections] [seconds] [host:port]");
+    let connections: usize = args.next().and_then(|x| x.parse().ok()).unwrap_or(200);
+    let seconds: u64 = args.next().and_then(|x| x.parse().ok()).unwrap_or(30);
+    let addr = args.next().unwrap_or("localhost:3000".to_string());
+
+    let game_id = create_game(&addr).await;
+    println!("Created game {game_id}, connecting {connections} spectators");
+
+    let mut clients = Vec::with_capacity(connections);
+    for i in 0..connections {
+        let url = format!("ws://{addr}/api/stream/games/{game_id}/Spectator/idle{i}");
+        let (ws, _) = tokio_tungstenite::connect_async(url)
+            .await
+            .expect("failed to connect spectator");
+        // Keep reading so pings are answered and the connection stays healthy
+        clients.push(tokio::spawn(ws.for_each(|_| async {})));
+    }
+
+    // Let the initial join messages settle before measuring
+    tokio::time::sleep(Duration::from_secs(2)).await;
+
+    let start_ticks = cpu_ticks(pid);
+    let start = Instant::now();
+    tokio::time::sleep(Duration::from_secs(seconds)).await;
+    let elapsed = start.elapsed().as_secs_f64();
+    let used = (cpu_ticks(pid) - start_ticks) as f64 / USER_HZ;
+
+    let percent = used / elapsed * 100.0;
+    println!("Server used {used:.3}s of CPU over {elapsed:.1}s ({percent:.3}% of a core)");
+    println!(
+        "Per idle connection: {:.3}ms of CPU per second",
+        used * 1000.0 / elapsed / connections as f64
+    );
+
+    for client in clients {
+        client.abort();
+    }
+}
+
+/// Creates a game with a plain HTTP request, to avoid pulling in an HTTP client.
+async fn create_game(addr: &str) -> String {
+    let mut stream = TcpStream::connect(addr)
+        .await
+        .expect("failed to connect to the server");
+    let request = format!(
+        "POST /api/games HTTP/1.1\r\nHost: {addr}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{GAME}",
+        GAME.len()
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let body = response.split("\r\n\r\n").nth(1).unwrap_or_default();
+    let created: serde_json::Value = serde_json::from_str(body).expect("unexpected response");
+    return created["id"].as_str().unwrap().to_string();
+}
+
+/// Total user and system CPU time used by the process, in clock ticks.
+fn cpu_ticks(pid: u32) -> u64 {
+    let stat =
+        fs::read_to_string(format!("/proc/{pid}/stat")).expect("failed to read process stats");
+    // The process name can contain spaces, so skip past it before splitting
+    let fields: Vec<&str> = stat[stat.rfind(')').unwrap() + 2..].split(' ').collect();
+    let utime: u64 = fields[11].parse().unwrap();
+    let stime: u64 = fields[12].parse().unwrap();
+    return utime + stime;
+}
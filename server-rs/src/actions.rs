@@ -1,14 +1,24 @@
 use std::borrow::Borrow;
 
 use crate::{
-    dto::{CreateGameRequest, GameMessage, GameOverview, UpdateGameRequest},
-    models::{AppState, Game, GameEntry, GameLog, GameState, Player, PlayerRole, Question},
-    util::get_time,
+    background,
+    dto::{
+        CreateGameRequest, CreateGameResponse, EndSessionReason, GameMessage, GameOverview,
+        UpdateGameRequest,
+    },
+    game_id::GameIdGenerator,
+    grading,
+    models::{
+        AnswerMode, AppState, Buzz, Category, FinalRound, Game, GameEntry, GameLog, GameState,
+        GameTimer, PausedTimer, Player, PlayerRole, Question, QuestionKind, SubmittedAnswer, Team,
+        TimerKind,
+    },
+    util::{generate_token, get_time},
 };
-use dashmap::mapref::one::RefMut;
+use dashmap::mapref::{entry::Entry, one::RefMut};
 use rand::seq::SliceRandom;
 use serde::{Deserialize, Serialize};
-use tokio::{sync::broadcast, time::Instant};
+use tokio::time::Instant;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum GameError {
@@ -22,59 +32,107 @@ pub enum GameError {
     MissingQuestions,
     NewPlayerCannotJoinAfterStart,
     AlreadyAnswered,
+    InvalidToken,
+    UsernameTaken,
+    InvalidWager,
+    MissingWagers,
+    TeamNotFound,
+    InvalidQuestionChoices,
+    ChoiceNotFound,
+    NothingToUndo,
+    PlayerBanned,
+    LastHost,
+    GamePaused,
+    ServerShuttingDown,
+    BoardNotFound,
+    InvalidBoardName,
+    InvalidCsv,
+    InvalidRoundNumber,
+    InvalidQuestionValue,
+    InvalidTimeLimit,
 }
 
 impl AppState {
-    pub fn create_game(self, request: CreateGameRequest) -> Result<Game, GameError> {
-        if request.rounds.is_empty()
-            || request.rounds.iter().any(|x| x.is_empty())
-            || request
-                .rounds
-                .iter()
-                .flat_map(|x| x)
-                .any(|x| x.questions.is_empty())
-            || request
-                .rounds
-                .iter()
-                .flat_map(|x| x)
-                .flat_map(|x| x.questions.iter())
-                .any(|x| x.correct_answer.is_empty() || x.detail.is_empty())
+    pub fn create_game(
+        self,
+        mut request: CreateGameRequest,
+    ) -> Result<CreateGameResponse, GameError> {
+        if self.is_shutting_down() {
+            return Err(GameError::ServerShuttingDown);
+        }
+
+        let mut board_id_used = None;
+        if let Some(board_id) = request.board_id.take() {
+            let board = self.get_board(&board_id)?;
+            request.rounds = board.rounds;
+            request.final_round = board.final_round;
+            request.settings = request.settings.or(Some(board.settings));
+            board_id_used = Some(board_id);
+        }
+
+        prepare_questions(&mut request.rounds, &mut request.final_round)?;
+        if request
+            .settings
+            .as_ref()
+            .is_some_and(|x| !x.has_valid_limits())
         {
-            return Err(GameError::MissingQuestions);
+            return Err(GameError::InvalidTimeLimit);
         }
-        // Chars A-Z in ASCII
-        let id_chars: Vec<char> = (65..90u32).map(|x| char::from_u32(x).unwrap()).collect();
-        let mut rng = rand::thread_rng();
-        let id: String = (0..4)
-            .map(|_x| id_chars.choose(&mut rng).unwrap_or(&'A'))
-            .collect::<String>();
 
         let mut game: Game = request.into();
-        game.id = id.clone();
-        let (sender, receiver) = broadcast::channel(16);
-        let game_entry = GameEntry {
-            game: game.clone(),
-            sender,
-            receiver,
-            last_updated: Instant::now(),
-        };
-        match self.games.insert(id, game_entry) {
-            Some(_) => return Err(GameError::FailedToCreateGame), // Game already exists, might be an ID clash. So fail.
-            None => return Ok(game),
+        for id in GameIdGenerator::new(&self.config) {
+            // Claim the ID while holding the lock, so a game that already has it is never replaced
+            let vacant_entry = match self.games.entry(id.clone()) {
+                Entry::Occupied(_) => {
+                    tracing::warn!("Game ID {id} is already in use, trying another");
+                    self.metrics.game_id_collisions.inc();
+                    continue;
+                }
+                Entry::Vacant(x) => x,
+            };
+
+            game.id = id.clone();
+            let mut game_entry = GameEntry::new(game.clone(), self.config.channel_capacity);
+            game_entry.auth.host_token = generate_token();
+            game_entry.auth.board_id = board_id_used;
+            let response = CreateGameResponse {
+                game,
+                host_token: game_entry.auth.host_token.clone(),
+            };
+            self.save_game(&game_entry);
+            let timer = game_entry.timer.subscribe();
+            vacant_entry.insert(game_entry);
+
+            background::start_game_timers(self.clone(), id, timer);
+            return Ok(response);
         }
+
+        tracing::error!("Failed to find a free game ID");
+        return Err(GameError::FailedToCreateGame);
+    }
+
+    /// Start tracking a game, along with the task that runs its timers.
+    pub fn insert_game(&self, game_entry: GameEntry) -> Option<GameEntry> {
+        let game_id = game_entry.game.id.clone();
+        background::start_game_timers(self.clone(), game_id.clone(), game_entry.timer.subscribe());
+        return self.games.insert(game_id, game_entry);
     }
 
+    /// Add a player to the game, or reconnect an existing one, returning the token the player
+    /// must use to identify themselves from now on.
     pub fn join_game(
         self,
         game_id: String,
         username: String,
         role: PlayerRole,
-    ) -> Result<(), GameError> {
-        if game_id.len() > 4 {
-            return Err(GameError::GameNotFound);
+        token: Option<String>,
+        team_id: Option<String>,
+    ) -> Result<String, GameError> {
+        if self.is_shutting_down() {
+            return Err(GameError::ServerShuttingDown);
         }
 
-        if username.len() > 20 {
+        if username.len() > self.config.max_username_length {
             return Err(GameError::UsernameTooLong);
         }
 
@@ -83,75 +141,326 @@ impl AppState {
             None => return Err(GameError::GameNotFound),
         };
 
-        let existing = get_player(&mut entry.game, username.clone());
-        if let Some(_player) = existing {
-            // If the player already exists, then don't add them again.
-            return Ok(());
+        if entry.auth.is_banned(&username) {
+            return Err(GameError::PlayerBanned);
+        }
+
+        let existing = get_player(&mut entry.game, username.clone()).map(|x| x.role.clone());
+        if let Some(existing_role) = existing {
+            // If the player already exists, then don't add them again, but only let them back in
+            // if they can prove they are the same player. The host secret can also be used to
+            // take back a Host's place, e.g. if the Host's device has died.
+            return match token {
+                Some(token) if entry.auth.get_username(&token) == Some(&username) => Ok(token),
+                Some(token)
+                    if token == entry.auth.host_token && existing_role == PlayerRole::Host =>
+                {
+                    let token = generate_token();
+                    entry
+                        .auth
+                        .player_tokens
+                        .insert(token.clone(), username.clone());
+                    Ok(token)
+                }
+                Some(_) => Err(GameError::InvalidToken),
+                None => Err(GameError::UsernameTaken),
+            };
         }
 
-        if role != PlayerRole::Spectator && entry.game.state != GameState::WaitingToStart {
+        // Anyone with the host secret may join as an additional Host. The first to join is the
+        // Host that requests made with the host secret itself are made as.
+        if role == PlayerRole::Host {
+            if token.as_ref() != Some(&entry.auth.host_token) {
+                return Err(GameError::InvalidToken);
+            }
+            if entry.auth.host_username.is_none() {
+                entry.auth.host_username = Some(username.clone());
+            }
+        } else if role != PlayerRole::Spectator && entry.game.state != GameState::WaitingToStart {
             return Err(GameError::NewPlayerCannotJoinAfterStart);
         }
 
+        // Contestants either pick their team, or are put in the smallest one
+        let team_id = match (&role, team_id) {
+            (PlayerRole::Contestant, Some(team_id)) => {
+                if !entry.game.teams.iter().any(|x| x.team_id == team_id) {
+                    return Err(GameError::TeamNotFound);
+                }
+                Some(team_id)
+            }
+            (PlayerRole::Contestant, None) => get_smallest_team(&entry.game),
+            _ => None,
+        };
+
         let game_entry = entry.value_mut();
+        let token = generate_token();
+        game_entry
+            .auth
+            .player_tokens
+            .insert(token.clone(), username.clone());
         let new_player = Player {
             username,
             role,
             score: 0,
+            team_id,
         };
         game_entry.game.players.push(new_player);
         game_entry.last_updated = Instant::now();
 
-        let _ = game_entry.sender.send(GameMessage::GameUpdate {
-            game: game_entry.game.borrow().into(),
-        });
+        let update: GameOverview = game_entry.game.borrow().into();
+        game_entry.broadcast(GameMessage::GameUpdate { game: update });
+        self.save_game(game_entry);
 
-        return Ok(());
+        return Ok(token);
+    }
+
+    /// Process a request from a player, recording metrics and saving the game if it changed.
+    pub fn process_game_request(
+        &self,
+        game_entry: &mut RefMut<String, GameEntry>,
+        token: String,
+        request: UpdateGameRequest,
+    ) -> Result<(), GameError> {
+        let request_type = request.kind();
+        let played_before = played_questions(&game_entry.game);
+        let start = Instant::now();
+        let result = handle_game_request(game_entry, token, request);
+
+        self.metrics
+            .game_requests
+            .with_label_values(&[request_type])
+            .inc();
+        self.metrics
+            .game_request_duration
+            .with_label_values(&[request_type])
+            .observe(start.elapsed().as_secs_f64());
+        match &result {
+            Ok(_) => {
+                self.save_game(game_entry);
+                self.record_board_usage(game_entry, &played_before);
+            }
+            Err(e) => self.metrics.record_error(e),
+        }
+        return result;
+    }
+
+    /// Handle a game's timer firing, saving the game if it changed.
+    pub fn process_timer(
+        &self,
+        game_entry: &mut RefMut<String, GameEntry>,
+        timer: GameTimer,
+    ) -> Result<(), GameError> {
+        let played_before = played_questions(&game_entry.game);
+        let result = handle_timer(game_entry, timer);
+        if result.is_ok() {
+            self.save_game(game_entry);
+            self.record_board_usage(game_entry, &played_before);
+        }
+        return result;
+    }
+
+    /// Keep track of which questions from the question bank get played, for games created from a
+    /// board. Questions that are no longer played, because the Host undid answering them, are
+    /// taken off again.
+    fn record_board_usage(&self, game_entry: &GameEntry, played_before: &[String]) {
+        let board_id = match &game_entry.auth.board_id {
+            Some(x) => x,
+            None => return,
+        };
+
+        let played_after = played_questions(&game_entry.game);
+        let played: Vec<&String> = played_after
+            .iter()
+            .filter(|x| !played_before.contains(x))
+            .collect();
+        let unplayed: Vec<&String> = played_before
+            .iter()
+            .filter(|x| !played_after.contains(x))
+            .collect();
+        if !played.is_empty() || !unplayed.is_empty() {
+            self.record_questions_played(board_id, &played, &unplayed);
+        }
+    }
+}
+
+/// IDs of the questions that have been played so far, which is once they have been answered or, for
+/// the final round, once the question has been shown.
+fn played_questions(game: &Game) -> Vec<String> {
+    let state = game.state.unpaused();
+    let final_question = game
+        .final_round
+        .iter()
+        .filter(|_| {
+            matches!(
+                state,
+                GameState::FinalRoundAnswer { .. }
+                    | GameState::FinalRoundReveal { .. }
+                    | GameState::Finished
+            )
+        })
+        .map(|x| &x.question);
+
+    return game
+        .rounds
+        .iter()
+        .flatten()
+        .flat_map(|x| x.questions.iter())
+        .filter(|x| x.answered)
+        .chain(final_question)
+        .map(|x| x.question_id.clone())
+        .collect();
+}
+
+/// Handle a game's timer firing, as scheduled by the game's timer task.
+fn handle_timer(
+    game_entry: &mut RefMut<String, GameEntry>,
+    timer: GameTimer,
+) -> Result<(), GameError> {
+    // The timer may have been replaced or cleared since it was scheduled
+    if game_entry.game.timer.as_ref() != Some(&timer) {
+        return Err(GameError::InvalidGameState);
+    }
+    let previous = game_entry.game.clone();
+    game_entry.set_timer(None);
+
+    let result = match timer.kind {
+        TimerKind::BuzzWindow => resolve_buzzes(game_entry),
+        TimerKind::BuzzTimeout => pass_question(game_entry),
+        TimerKind::AnswerTimeout => grade_answer(game_entry, false),
+    };
+
+    if result.is_ok() {
+        // Timeouts stand in for the Host passing the question or marking the answer, so they can
+        // be undone like the Host's own actions. Closing the buzz window only settles buzzes.
+        if timer.kind != TimerKind::BuzzWindow {
+            game_entry.remember_for_undo(previous);
+        }
+        let update: GameOverview = game_entry.game.borrow().into();
+        game_entry.broadcast(GameMessage::GameUpdate { game: update });
     }
+    return result;
 }
 
 pub fn handle_game_request(
     game_entry: &mut RefMut<String, GameEntry>,
-    username: String,
+    token: String,
     request: UpdateGameRequest,
-) -> () {
+) -> Result<(), GameError> {
     game_entry.last_updated = Instant::now();
-    let current_player = get_player(&mut game_entry.game, username.clone());
-    let role = match current_player {
+    // The player is identified by their token rather than anything they claim about themselves
+    let username = match game_entry.auth.get_username(&token) {
+        Some(x) => x.clone(),
+        None => return Err(GameError::InvalidToken),
+    };
+    let role = match get_player(&mut game_entry.game, username.clone()) {
         Some(player) => player.role.clone(),
-        None => PlayerRole::Contestant,
+        None => return Err(GameError::PlayerNotFound),
     };
 
+    // Remember the game before a Host's request so it can be undone
+    let is_undoable = role == PlayerRole::Host && is_undoable(&request);
+    let previous = match is_undoable {
+        true => Some(game_entry.game.clone()),
+        false => None,
+    };
+
+    // Only Hosts can do anything while the game is paused, other than players leaving
+    let is_paused = matches!(game_entry.game.state, GameState::Paused { .. })
+        && role != PlayerRole::Host
+        && !matches!(request, UpdateGameRequest::LeaveGame);
+
     let result = match request {
+        _ if is_paused => Err(GameError::GamePaused),
         UpdateGameRequest::StartGame => start_game(game_entry, role),
         UpdateGameRequest::LeaveGame => leave_game(game_entry, username.clone()),
-        UpdateGameRequest::PickQuestion { question_id } => pick_question(game_entry, question_id),
+        UpdateGameRequest::PickQuestion { question_id } => {
+            pick_question(game_entry, role, question_id)
+        }
         UpdateGameRequest::AllowAnswering => allow_answering(game_entry, role),
         UpdateGameRequest::AnswerQuestion => answer_question(game_entry, username.clone()),
-        UpdateGameRequest::ConfirmAnswer { is_correct } => confirm_answer(game_entry, is_correct),
+        UpdateGameRequest::ConfirmAnswer { is_correct } => {
+            confirm_answer(game_entry, role, is_correct)
+        }
         UpdateGameRequest::EndQuestion => end_question(game_entry, role),
         UpdateGameRequest::UpdatePlayerScore {
             update_username,
             new_score,
         } => update_player_score(game_entry, role, update_username, new_score),
+        UpdateGameRequest::SubmitWager { amount } => {
+            submit_wager(game_entry, role, username.clone(), amount)
+        }
+        UpdateGameRequest::ShowFinalQuestion => show_final_question(game_entry, role),
+        UpdateGameRequest::SubmitFinalAnswer { answer } => {
+            submit_final_answer(game_entry, username.clone(), answer)
+        }
+        UpdateGameRequest::RevealFinalAnswer => reveal_final_answer(game_entry, role),
+        UpdateGameRequest::ScoreFinalAnswer { is_correct } => {
+            score_final_answer(game_entry, role, is_correct)
+        }
+        UpdateGameRequest::CreateTeam { name } => create_team(game_entry, role, name),
+        UpdateGameRequest::RenameTeam { team_id, name } => {
+            rename_team(game_entry, role, team_id, name)
+        }
+        UpdateGameRequest::RemoveTeam { team_id } => remove_team(game_entry, role, team_id),
+        UpdateGameRequest::AssignTeam {
+            update_username,
+            team_id,
+        } => assign_team(game_entry, role, update_username, team_id),
+        UpdateGameRequest::BalanceTeams => balance_teams(game_entry, role),
+        UpdateGameRequest::SubmitAnswer { answer } => {
+            submit_answer(game_entry, username.clone(), answer)
+        }
+        UpdateGameRequest::SubmitChoice { index } => {
+            submit_choice(game_entry, username.clone(), index)
+        }
+        UpdateGameRequest::UndoLastAction => undo_last_action(game_entry, role, username.clone()),
+        UpdateGameRequest::KickPlayer { kick_username } => {
+            kick_player(game_entry, role, kick_username, EndSessionReason::Kicked)
+        }
+        UpdateGameRequest::BanPlayer { ban_username } => {
+            kick_player(game_entry, role, ban_username, EndSessionReason::Banned)
+        }
+        UpdateGameRequest::PromotePlayer { promote_username } => {
+            change_role(game_entry, role, promote_username, PlayerRole::Host)
+        }
+        UpdateGameRequest::DemotePlayer { demote_username } => {
+            change_role(game_entry, role, demote_username, PlayerRole::Spectator)
+        }
+        UpdateGameRequest::PauseGame => pause_game(game_entry, role),
+        UpdateGameRequest::ResumeGame => resume_game(game_entry, role),
     };
 
-    match result {
+    match &result {
         Ok(_) => {
+            if let Some(previous) = previous {
+                game_entry.remember_for_undo(previous);
+            }
             let update: GameOverview = game_entry.game.borrow().into();
-            let _ = game_entry
-                .sender
-                .send(GameMessage::GameUpdate { game: update });
-        }
-        Err(e) => {
-            tracing::error!("Failed to process the game request: {e:?}");
-            let _ = game_entry.sender.send(GameMessage::ReportError {
-                message: e.get_message(),
-                error: e,
-                username,
-            });
+            game_entry.broadcast(GameMessage::GameUpdate { game: update });
         }
+        Err(e) => tracing::error!("Failed to process the game request from {username}: {e:?}"),
     }
+    return result;
+}
+
+/// Whether a Host's request can be undone. Contestants' actions, such as buzzing in or wagering,
+/// aren't undone on their own. Changes to who is in the game, what they can do and which team they
+/// are on are left out, as undoing them would leave sessions out of step with the game.
+fn is_undoable(request: &UpdateGameRequest) -> bool {
+    return !matches!(
+        request,
+        UpdateGameRequest::UndoLastAction
+            | UpdateGameRequest::LeaveGame
+            | UpdateGameRequest::KickPlayer { .. }
+            | UpdateGameRequest::BanPlayer { .. }
+            | UpdateGameRequest::PromotePlayer { .. }
+            | UpdateGameRequest::DemotePlayer { .. }
+            | UpdateGameRequest::CreateTeam { .. }
+            | UpdateGameRequest::RenameTeam { .. }
+            | UpdateGameRequest::RemoveTeam { .. }
+            | UpdateGameRequest::AssignTeam { .. }
+            | UpdateGameRequest::BalanceTeams
+    );
 }
 
 fn start_game(
@@ -179,8 +488,9 @@ fn leave_game(
     username: String,
 ) -> Result<(), GameError> {
     // Send a message to the websocket to tell it to cleanly close the session for the specified user
-    let _ = game_entry.sender.send(GameMessage::EndSession {
+    game_entry.broadcast(GameMessage::EndSession {
         username: username.clone(),
+        reason: EndSessionReason::Left,
     });
 
     if game_entry.game.state == GameState::WaitingToStart {
@@ -192,15 +502,225 @@ fn leave_game(
 
         if let Some(index) = player_index {
             game_entry.game.players.remove(index);
+            game_entry
+                .auth
+                .player_tokens
+                .retain(|_token, player| player != &username);
+            // Let the next Host to join take over requests made with the host secret
+            if game_entry.auth.host_username.as_ref() == Some(&username) {
+                game_entry.auth.host_username = None;
+            }
+        }
+    }
+    return Ok(());
+}
+
+/// Remove a player from the game and end their session. Banned players also can't join again.
+fn kick_player(
+    game_entry: &mut RefMut<String, GameEntry>,
+    role: PlayerRole,
+    username: String,
+    reason: EndSessionReason,
+) -> Result<(), GameError> {
+    if role != PlayerRole::Host {
+        return Err(GameError::InsufficientPermissions);
+    }
+
+    let player = match get_player(&mut game_entry.game, username.clone()) {
+        Some(x) => x.clone(),
+        None => return Err(GameError::PlayerNotFound),
+    };
+    if player.role == PlayerRole::Host {
+        return Err(GameError::InsufficientPermissions);
+    }
+
+    release_contestant(game_entry, &username);
+    game_entry.game.players.retain(|x| x.username != username);
+    game_entry
+        .auth
+        .player_tokens
+        .retain(|_token, player| player != &username);
+    game_entry.latencies.remove(&username);
+
+    let time = get_time();
+    if reason == EndSessionReason::Banned {
+        game_entry.auth.banned_usernames.push(username.clone());
+        game_entry.game.log.push(GameLog::PlayerBanned {
+            time,
+            username: username.clone(),
+        });
+    } else {
+        game_entry.game.log.push(GameLog::PlayerKicked {
+            time,
+            username: username.clone(),
+        });
+    }
+
+    game_entry.broadcast(GameMessage::EndSession { username, reason });
+    return Ok(());
+}
+
+/// Change a player's role, as long as the game is always left with a Host.
+fn change_role(
+    game_entry: &mut RefMut<String, GameEntry>,
+    role: PlayerRole,
+    username: String,
+    new_role: PlayerRole,
+) -> Result<(), GameError> {
+    if role != PlayerRole::Host {
+        return Err(GameError::InsufficientPermissions);
+    }
+
+    let player = match get_player(&mut game_entry.game, username.clone()) {
+        Some(x) => x.clone(),
+        None => return Err(GameError::PlayerNotFound),
+    };
+    if player.role == new_role {
+        return Ok(());
+    }
+
+    let host_count = game_entry
+        .game
+        .players
+        .iter()
+        .filter(|x| x.role == PlayerRole::Host)
+        .count();
+    if player.role == PlayerRole::Host && host_count <= 1 {
+        return Err(GameError::LastHost);
+    }
+
+    if player.role == PlayerRole::Contestant {
+        release_contestant(game_entry, &username);
+    }
+    if let Some(player) = get_player(&mut game_entry.game, username.clone()) {
+        player.role = new_role.clone();
+        player.team_id = None;
+    }
+    game_entry.game.log.push(GameLog::RoleChanged {
+        time: get_time(),
+        username,
+        role: new_role,
+    });
+
+    // The answers on the board are only sent to Hosts, so everyone needs the board again
+    let resync = Box::new(game_entry.game.clone());
+    game_entry.broadcast(GameMessage::Resync { game: resync });
+    return Ok(());
+}
+
+/// Stop the game from waiting on a contestant who can no longer play, either because they have been
+/// removed or because their role has changed. While paused, the state the game resumes to is
+/// changed instead.
+fn release_contestant(game_entry: &mut RefMut<String, GameEntry>, username: &str) {
+    let (state, is_paused) = match game_entry.game.state.clone() {
+        GameState::Paused { previous, .. } => (*previous, true),
+        x => (x, false),
+    };
+
+    let released = match state {
+        GameState::CheckAnswer {
+            question,
+            player,
+            wager: None,
+            ..
+        } if player.username == username => {
+            // Let the other contestants buzz in instead
+            Some(GameState::WaitingForAnswer { question })
+        }
+        GameState::CheckAnswer { player, .. } | GameState::DailyDoubleWager { player, .. }
+            if player.username == username =>
+        {
+            // Nobody else can play a daily double, so leave it on the board to be picked again
+            Some(GameState::PickAQuestion)
+        }
+        _ => None,
+    };
+
+    if let Some(state) = released {
+        let limit = match state {
+            GameState::WaitingForAnswer { .. } => game_entry.game.settings.buzz_time_limit_secs,
+            _ => None,
+        };
+        if is_paused {
+            // The new countdown starts once the game is resumed
+            game_entry.game.state = GameState::Paused {
+                previous: Box::new(state),
+                timer: limit.map(|x| PausedTimer {
+                    kind: TimerKind::BuzzTimeout,
+                    remaining_ms: x.saturating_mul(1000),
+                }),
+            };
+        } else {
+            game_entry.game.state = state;
+            start_countdown(game_entry, TimerKind::BuzzTimeout, limit);
         }
     }
+
+    game_entry.buzzes.retain(|x| x.username != username);
+    if let Some(final_round) = &mut game_entry.game.final_round {
+        final_round.wagers.remove(username);
+        final_round.answers.remove(username);
+    }
+}
+
+fn pause_game(
+    game_entry: &mut RefMut<String, GameEntry>,
+    role: PlayerRole,
+) -> Result<(), GameError> {
+    if role != PlayerRole::Host {
+        return Err(GameError::InsufficientPermissions);
+    }
+
+    if matches!(game_entry.game.state, GameState::Paused { .. }) {
+        return Err(GameError::InvalidGameState);
+    }
+
+    // Stop the clock, remembering how long was left
+    let now = get_time();
+    let timer = game_entry.game.timer.clone().map(|x| PausedTimer {
+        kind: x.kind,
+        remaining_ms: x.deadline.saturating_sub(now),
+    });
+    game_entry.set_timer(None);
+
+    let previous = Box::new(game_entry.game.state.clone());
+    game_entry.game.state = GameState::Paused { previous, timer };
+    game_entry.game.log.push(GameLog::GamePaused { time: now });
+    return Ok(());
+}
+
+fn resume_game(
+    game_entry: &mut RefMut<String, GameEntry>,
+    role: PlayerRole,
+) -> Result<(), GameError> {
+    if role != PlayerRole::Host {
+        return Err(GameError::InsufficientPermissions);
+    }
+
+    let (previous, timer) = match game_entry.game.state.clone() {
+        GameState::Paused { previous, timer } => (previous, timer),
+        _ => return Err(GameError::InvalidGameState),
+    };
+
+    let now = get_time();
+    game_entry.game.state = *previous;
+    game_entry.set_timer(timer.map(|x| GameTimer {
+        kind: x.kind,
+        deadline: now.saturating_add(x.remaining_ms),
+    }));
+    game_entry.game.log.push(GameLog::GameResumed { time: now });
     return Ok(());
 }
 
 fn pick_question(
     game_entry: &mut RefMut<String, GameEntry>,
+    role: PlayerRole,
     question_id: String,
 ) -> Result<(), GameError> {
+    if role != PlayerRole::Host {
+        return Err(GameError::InsufficientPermissions);
+    }
+
     if game_entry.game.state != GameState::PickAQuestion {
         return Err(GameError::InvalidGameState);
     }
@@ -213,12 +733,32 @@ fn pick_question(
         .find(|x| x.question_id == question_id && x.answered == false);
 
     if let Some(question) = question {
-        game.state = GameState::ReadQuestion {
-            question: question.clone(),
+        // Shuffle a copy of the choices, so the order on the board can't give the answer away
+        let mut question = question.clone();
+        if question.kind == QuestionKind::MultipleChoice {
+            question.choices.shuffle(&mut rand::thread_rng());
+        }
+
+        // A daily double goes to the player who won the last question. If nobody has won a question
+        // yet then there is nobody to give it to, so it is played like any other question.
+        let daily_double_player = game
+            .players
+            .iter()
+            .find(|x| x.username == game.last_winner && x.role == PlayerRole::Contestant)
+            .filter(|_| question.daily_double);
+
+        game.state = match daily_double_player {
+            Some(player) => GameState::DailyDoubleWager {
+                question: question.clone(),
+                player: player.clone(),
+            },
+            None => GameState::ReadQuestion {
+                question: question.clone(),
+            },
         };
         game.log.push(GameLog::QuestionPicked {
             time: get_time(),
-            question_id: question.clone().question_id,
+            question_id: question.question_id,
         });
     } else {
         return Err(GameError::QuestionNotFound);
@@ -239,6 +779,8 @@ fn allow_answering(
         game_entry.game.state = GameState::WaitingForAnswer {
             question: question.clone(),
         };
+        let limit = game_entry.game.settings.buzz_time_limit_secs;
+        start_countdown(game_entry, TimerKind::BuzzTimeout, limit);
     } else {
         return Err(GameError::InvalidGameState);
     }
@@ -250,28 +792,53 @@ fn answer_question(
     game_entry: &mut RefMut<String, GameEntry>,
     username: String,
 ) -> Result<(), GameError> {
-    let game = &mut game_entry.value_mut().game;
-    match game.state.clone() {
-        GameState::WaitingForAnswer { question } => {
-            let player = match game.players.iter().find(|x| x.username == username) {
-                Some(x) => x,
-                None => return Err(GameError::PlayerNotFound),
-            };
+    match game_entry.game.state {
+        GameState::WaitingForAnswer { .. } => {
+            if !game_entry
+                .game
+                .players
+                .iter()
+                .any(|x| x.username == username)
+            {
+                return Err(GameError::PlayerNotFound);
+            }
 
-            game.state = GameState::CheckAnswer {
-                question: question.clone(),
-                player: player.clone(),
-            };
+            // Only the first buzz from each player counts, or from each team when playing in teams
+            let team_id = get_team_id(&game_entry.game, &username);
+            if game_entry.buzzes.iter().any(|buzz| {
+                buzz.username == username
+                    || (team_id.is_some()
+                        && get_team_id(&game_entry.game, &buzz.username) == team_id)
+            }) {
+                return Ok(());
+            }
 
-            game.log.push(GameLog::PlayerBuzzedIn {
-                time: get_time(),
-                username: username.clone(),
+            let settings = game_entry.game.settings.clone();
+            let latency_compensation = match settings.latency_compensation {
+                true => game_entry.latencies.get(&username).cloned().unwrap_or(0) / 2,
+                false => 0,
+            };
+            let now = get_time();
+            game_entry.buzzes.push(Buzz {
+                username,
+                received_at: now,
+                // Never compensate by more than the window, or a slow connection could win every time
+                latency_compensation: latency_compensation.min(settings.buzz_window_ms),
             });
+
+            if settings.buzz_window_ms == 0 {
+                return resolve_buzzes(game_entry);
+            }
+
+            // The first buzz opens the window, and the winner is decided when it closes
+            if game_entry.buzzes.len() == 1 {
+                game_entry.set_timer(Some(GameTimer {
+                    kind: TimerKind::BuzzWindow,
+                    deadline: now.saturating_add(settings.buzz_window_ms),
+                }));
+            }
         }
-        GameState::CheckAnswer {
-            question: _,
-            player: _,
-        } => {
+        GameState::CheckAnswer { .. } => {
             return Err(GameError::AlreadyAnswered);
         }
         _ => return Err(GameError::InvalidGameState),
@@ -280,35 +847,95 @@ fn answer_question(
     return Ok(());
 }
 
+/// Award the question to the earliest buzz once latency has been accounted for.
+fn resolve_buzzes(game_entry: &mut GameEntry) -> Result<(), GameError> {
+    let question = match &game_entry.game.state {
+        GameState::WaitingForAnswer { question } => question.clone(),
+        _ => return Err(GameError::InvalidGameState),
+    };
+
+    let mut buzzes = std::mem::take(&mut game_entry.buzzes);
+    buzzes.sort_by_key(|x| x.received_at.saturating_sub(x.latency_compensation));
+
+    let game = &mut game_entry.game;
+    let player = buzzes
+        .iter()
+        .find_map(|buzz| game.players.iter().find(|x| x.username == buzz.username));
+    let player = match player {
+        Some(x) => x.clone(),
+        None => return Err(GameError::PlayerNotFound),
+    };
+
+    game.log.push(GameLog::PlayerBuzzedIn {
+        time: get_time(),
+        username: player.username.clone(),
+        buzzes,
+    });
+    game.state = GameState::CheckAnswer {
+        question,
+        player,
+        wager: None,
+        submitted_answer: None,
+    };
+    let limit = game.settings.answer_time_limit_secs;
+    start_countdown(game_entry, TimerKind::AnswerTimeout, limit);
+
+    return Ok(());
+}
+
 fn confirm_answer(
+    game_entry: &mut RefMut<String, GameEntry>,
+    role: PlayerRole,
+    is_correct: bool,
+) -> Result<(), GameError> {
+    if role != PlayerRole::Host {
+        return Err(GameError::InsufficientPermissions);
+    }
+
+    return grade_answer(game_entry, is_correct);
+}
+
+/// Score the answer of the player who buzzed in, as decided by the Host, the server or a timeout.
+fn grade_answer(
     game_entry: &mut RefMut<String, GameEntry>,
     is_correct: bool,
 ) -> Result<(), GameError> {
     let state = game_entry.game.state.clone();
-    if let GameState::CheckAnswer { question, player } = state {
-        let player_to_update = match get_player(&mut game_entry.game, player.username) {
-            Some(x) => x,
-            None => return Err(GameError::PlayerNotFound),
-        };
-        let username = player_to_update.username.clone();
+    if let GameState::CheckAnswer {
+        question,
+        player,
+        wager,
+        submitted_answer: _,
+    } = state
+    {
+        let username = player.username;
+        let points_change = wager.unwrap_or(question.value);
 
         if is_correct {
-            player_to_update.score += question.value;
+            award_points(&mut game_entry.game, username.clone(), points_change)?;
             game_entry.game.last_winner = username.clone();
             mark_question_answered(game_entry, question.question_id.clone())?;
         } else {
-            player_to_update.score -= question.value;
-            game_entry.game.state = GameState::WaitingForAnswer {
-                question: question.clone(),
-            };
+            award_points(&mut game_entry.game, username.clone(), -points_change)?;
+            if wager.is_some() {
+                // Nobody else gets a chance to answer a daily double once it has been wagered on
+                mark_question_answered(game_entry, question.question_id.clone())?;
+            } else {
+                game_entry.game.state = GameState::WaitingForAnswer {
+                    question: question.clone(),
+                };
+                let limit = game_entry.game.settings.buzz_time_limit_secs;
+                start_countdown(game_entry, TimerKind::BuzzTimeout, limit);
+            }
         }
 
         game_entry.game.log.push(GameLog::AnswerConfirmed {
             time: get_time(),
             username,
             is_correct,
-            points_change: question.value,
+            points_change,
             question_id: question.question_id,
+            wager,
         });
     } else {
         return Err(GameError::InvalidGameState);
@@ -317,24 +944,179 @@ fn confirm_answer(
     return Ok(());
 }
 
-fn end_question(
+fn submit_answer(
     game_entry: &mut RefMut<String, GameEntry>,
-    role: PlayerRole,
+    username: String,
+    answer: String,
 ) -> Result<(), GameError> {
-    if role != PlayerRole::Host {
+    let answer_mode = game_entry.game.settings.answer_mode.clone();
+    if answer_mode == AnswerMode::Spoken {
+        return Err(GameError::InvalidGameState);
+    }
+
+    let (question, player, wager) = match game_entry.game.state.clone() {
+        GameState::CheckAnswer {
+            question,
+            player,
+            wager,
+            submitted_answer: None,
+        } if question.kind == QuestionKind::FreeResponse => (question, player, wager),
+        _ => return Err(GameError::InvalidGameState),
+    };
+
+    // Only the player who buzzed in gets to answer
+    if player.username != username {
         return Err(GameError::InsufficientPermissions);
     }
 
-    if let GameState::WaitingForAnswer { question } = game_entry.game.state.clone() {
-        if let Err(e) = mark_question_answered(game_entry, question.question_id.clone()) {
-            return Err(e);
-        }
-        game_entry.game.log.push(GameLog::QuestionPassed {
-            time: get_time(),
-            question_id: question.question_id,
-        });
-    } else {
-        return Err(GameError::InvalidGameState);
+    let suggested_correct = grading::is_match(
+        &answer,
+        std::iter::once(question.correct_answer.as_str())
+            .chain(question.alternate_answers.iter().map(|x| x.as_str())),
+    );
+    game_entry.game.log.push(GameLog::AnswerSubmitted {
+        time: get_time(),
+        username,
+        answer: answer.clone(),
+        suggested_correct,
+    });
+
+    if answer_mode == AnswerMode::AutoGrade {
+        return grade_answer(game_entry, suggested_correct);
+    }
+
+    game_entry.game.state = GameState::CheckAnswer {
+        question,
+        player,
+        wager,
+        submitted_answer: Some(SubmittedAnswer {
+            answer,
+            suggested_correct,
+        }),
+    };
+    // The player answered in time, so it is now up to the Host
+    game_entry.set_timer(None);
+
+    return Ok(());
+}
+
+fn submit_choice(
+    game_entry: &mut RefMut<String, GameEntry>,
+    username: String,
+    index: usize,
+) -> Result<(), GameError> {
+    let (question, player) = match &game_entry.game.state {
+        GameState::CheckAnswer {
+            question,
+            player,
+            wager: _,
+            submitted_answer: None,
+        } if question.kind != QuestionKind::FreeResponse => (question, player),
+        _ => return Err(GameError::InvalidGameState),
+    };
+
+    // Only the player who buzzed in gets to answer
+    if player.username != username {
+        return Err(GameError::InsufficientPermissions);
+    }
+
+    let answer = match question.choices.get(index) {
+        Some(x) => x.clone(),
+        None => return Err(GameError::ChoiceNotFound),
+    };
+    let is_correct = answer == question.correct_answer;
+    game_entry.game.log.push(GameLog::AnswerSubmitted {
+        time: get_time(),
+        username,
+        answer,
+        suggested_correct: is_correct,
+    });
+
+    return grade_answer(game_entry, is_correct);
+}
+
+fn undo_last_action(
+    game_entry: &mut RefMut<String, GameEntry>,
+    role: PlayerRole,
+    username: String,
+) -> Result<(), GameError> {
+    if role != PlayerRole::Host {
+        return Err(GameError::InsufficientPermissions);
+    }
+
+    let mut game = match game_entry.undo_history.pop_back() {
+        Some(x) => x,
+        None => return Err(GameError::NothingToUndo),
+    };
+
+    // Keep the record of everything that happened. Only scores and the state of the game are undone,
+    // so the players and teams stay as they are now, other than their scores.
+    game.log = std::mem::take(&mut game_entry.game.log);
+    game.log.push(GameLog::ActionUndone {
+        time: get_time(),
+        username,
+    });
+    game.players = game_entry
+        .game
+        .players
+        .iter()
+        .map(|player| Player {
+            score: game
+                .players
+                .iter()
+                .find(|x| x.username == player.username)
+                .map_or(player.score, |x| x.score),
+            ..player.clone()
+        })
+        .collect();
+    game.teams = game_entry
+        .game
+        .teams
+        .iter()
+        .map(|team| Team {
+            score: game
+                .teams
+                .iter()
+                .find(|x| x.team_id == team.team_id)
+                .map_or(team.score, |x| x.score),
+            ..team.clone()
+        })
+        .collect();
+    game_entry.game = game;
+
+    // Countdowns from before the undo may have already run out, so let the host carry on manually
+    game_entry.buzzes.clear();
+    game_entry.set_timer(None);
+
+    // Questions may have been re-opened, so send everyone the whole board
+    let resync = Box::new(game_entry.game.clone());
+    game_entry.broadcast(GameMessage::Resync { game: resync });
+    return Ok(());
+}
+
+fn end_question(
+    game_entry: &mut RefMut<String, GameEntry>,
+    role: PlayerRole,
+) -> Result<(), GameError> {
+    if role != PlayerRole::Host {
+        return Err(GameError::InsufficientPermissions);
+    }
+
+    return pass_question(game_entry);
+}
+
+/// End the current question without anyone answering it correctly.
+fn pass_question(game_entry: &mut RefMut<String, GameEntry>) -> Result<(), GameError> {
+    if let GameState::WaitingForAnswer { question } = game_entry.game.state.clone() {
+        if let Err(e) = mark_question_answered(game_entry, question.question_id.clone()) {
+            return Err(e);
+        }
+        game_entry.game.log.push(GameLog::QuestionPassed {
+            time: get_time(),
+            question_id: question.question_id,
+        });
+    } else {
+        return Err(GameError::InvalidGameState);
     }
 
     return Ok(());
@@ -350,29 +1132,575 @@ fn update_player_score(
         return Err(GameError::InsufficientPermissions);
     }
 
-    let player_to_update = get_player(&mut game_entry.game, update_username.clone());
+    let player_to_update = get_player(&mut game_entry.game, update_username.clone());
+
+    match player_to_update {
+        None => return Err(GameError::PlayerNotFound),
+        Some(player) => {
+            let old_score = player.score;
+            // Apply the difference so the player's team total stays in step
+            award_points(
+                &mut game_entry.game,
+                update_username.clone(),
+                new_score - old_score,
+            )?;
+            game_entry.game.log.push(GameLog::ManualScoreUpdated {
+                time: get_time(),
+                username: update_username,
+                old_score: old_score,
+                new_score: new_score,
+            })
+        }
+    }
+
+    return Ok(());
+}
+
+fn submit_wager(
+    game_entry: &mut RefMut<String, GameEntry>,
+    role: PlayerRole,
+    username: String,
+    amount: isize,
+) -> Result<(), GameError> {
+    if role != PlayerRole::Contestant {
+        return Err(GameError::InsufficientPermissions);
+    }
+
+    match game_entry.game.state {
+        GameState::DailyDoubleWager { .. } => {
+            return submit_daily_double_wager(game_entry, username, amount)
+        }
+        GameState::FinalRoundWager { .. } => {}
+        _ => return Err(GameError::InvalidGameState),
+    }
+
+    let score = match get_score(&game_entry.game, &username) {
+        Some(x) => x,
+        None => return Err(GameError::PlayerNotFound),
+    };
+    if amount < 0 || amount > score.max(0) {
+        return Err(GameError::InvalidWager);
+    }
+
+    // Teams make a single wager between them, so a teammate's wager replaces the team's last one
+    let game = &mut game_entry.game;
+    let entrant = get_final_round_entrant(game, &username);
+    let final_round = match game.final_round.as_mut() {
+        Some(x) => x,
+        None => return Err(GameError::InvalidGameState),
+    };
+    if let Some(entrant) = entrant {
+        final_round.wagers.remove(&entrant);
+    }
+    final_round.wagers.insert(username.clone(), amount);
+    game.log.push(GameLog::WagerSubmitted {
+        time: get_time(),
+        username,
+    });
+
+    return Ok(());
+}
+
+fn submit_daily_double_wager(
+    game_entry: &mut RefMut<String, GameEntry>,
+    username: String,
+    amount: isize,
+) -> Result<(), GameError> {
+    let game = &mut game_entry.game;
+    let (question, player) = match game.state.clone() {
+        GameState::DailyDoubleWager { question, player } => (question, player),
+        _ => return Err(GameError::InvalidGameState),
+    };
+
+    if player.username != username {
+        return Err(GameError::InsufficientPermissions);
+    }
+
+    // Players can always wager up to the top value in the round, even if their score is lower
+    let score = match get_score(game, &username) {
+        Some(x) => x,
+        None => return Err(GameError::PlayerNotFound),
+    };
+    let top_value = game.rounds[game.current_round]
+        .iter()
+        .flat_map(|x| x.questions.iter())
+        .map(|x| x.value)
+        .max()
+        .unwrap_or(0);
+    if amount < 5 || amount > score.max(top_value).max(5) {
+        return Err(GameError::InvalidWager);
+    }
+
+    game.state = GameState::CheckAnswer {
+        question,
+        player,
+        wager: Some(amount),
+        submitted_answer: None,
+    };
+    game.log.push(GameLog::WagerSubmitted {
+        time: get_time(),
+        username,
+    });
+    let limit = game.settings.answer_time_limit_secs;
+    start_countdown(game_entry, TimerKind::AnswerTimeout, limit);
+
+    return Ok(());
+}
+
+fn show_final_question(
+    game_entry: &mut RefMut<String, GameEntry>,
+    role: PlayerRole,
+) -> Result<(), GameError> {
+    if role != PlayerRole::Host {
+        return Err(GameError::InsufficientPermissions);
+    }
+
+    let game = &mut game_entry.game;
+    let category = match &game.state {
+        GameState::FinalRoundWager { category } => category.clone(),
+        _ => return Err(GameError::InvalidGameState),
+    };
+    let final_round = match game.final_round.as_ref() {
+        Some(x) => x,
+        None => return Err(GameError::InvalidGameState),
+    };
+
+    if game
+        .players
+        .iter()
+        .filter(|x| x.role == PlayerRole::Contestant)
+        .any(|x| get_final_round_entrant(game, &x.username).is_none())
+    {
+        return Err(GameError::MissingWagers);
+    }
+
+    game.state = GameState::FinalRoundAnswer {
+        category,
+        question: final_round.question.clone(),
+    };
+    game.log.push(GameLog::QuestionPicked {
+        time: get_time(),
+        question_id: final_round.question.question_id.clone(),
+    });
+
+    return Ok(());
+}
+
+fn submit_final_answer(
+    game_entry: &mut RefMut<String, GameEntry>,
+    username: String,
+    answer: String,
+) -> Result<(), GameError> {
+    let game = &mut game_entry.game;
+    if !matches!(game.state, GameState::FinalRoundAnswer { .. }) {
+        return Err(GameError::InvalidGameState);
+    }
+
+    // Only contestants who placed a wager, or whose team did, are playing the final round
+    let entrant = match get_final_round_entrant(game, &username) {
+        Some(x) => x,
+        None => return Err(GameError::InsufficientPermissions),
+    };
+    let final_round = match game.final_round.as_mut() {
+        Some(x) => x,
+        None => return Err(GameError::InvalidGameState),
+    };
+
+    final_round.answers.insert(entrant, answer);
+    game.log.push(GameLog::FinalAnswerSubmitted {
+        time: get_time(),
+        username,
+    });
+
+    return Ok(());
+}
+
+fn reveal_final_answer(
+    game_entry: &mut RefMut<String, GameEntry>,
+    role: PlayerRole,
+) -> Result<(), GameError> {
+    if role != PlayerRole::Host {
+        return Err(GameError::InsufficientPermissions);
+    }
+
+    // Revealing the first answer locks in all answers
+    if !matches!(game_entry.game.state, GameState::FinalRoundAnswer { .. }) {
+        return Err(GameError::InvalidGameState);
+    }
+
+    return reveal_next_final_answer(&mut game_entry.game);
+}
+
+fn score_final_answer(
+    game_entry: &mut RefMut<String, GameEntry>,
+    role: PlayerRole,
+    is_correct: bool,
+) -> Result<(), GameError> {
+    if role != PlayerRole::Host {
+        return Err(GameError::InsufficientPermissions);
+    }
+
+    let game = &mut game_entry.game;
+    let (question, username, wager) = match game.state.clone() {
+        GameState::FinalRoundReveal {
+            question,
+            username,
+            answer: _,
+            wager,
+        } => (question, username, wager),
+        _ => return Err(GameError::InvalidGameState),
+    };
+
+    let points = match is_correct {
+        true => wager,
+        false => -wager,
+    };
+    award_points(game, username.clone(), points)?;
+
+    game.log.push(GameLog::AnswerConfirmed {
+        time: get_time(),
+        username,
+        is_correct,
+        points_change: wager,
+        question_id: question.question_id,
+        wager: Some(wager),
+    });
+
+    return reveal_next_final_answer(game);
+}
+
+/// Move on to the next final round answer to reveal, starting from the lowest scoring contestant or
+/// team, or finish the game once every answer has been revealed.
+fn reveal_next_final_answer(game: &mut Game) -> Result<(), GameError> {
+    let final_round = match game.final_round.as_ref() {
+        Some(x) => x,
+        None => return Err(GameError::InvalidGameState),
+    };
+
+    let next = game
+        .players
+        .iter()
+        .filter(|x| final_round.wagers.contains_key(&x.username))
+        .filter(|x| !final_round.revealed.contains(&x.username))
+        .min_by_key(|x| get_score(game, &x.username));
+
+    let username = match next {
+        Some(x) => x.username.clone(),
+        None => {
+            game.state = GameState::Finished;
+            return Ok(());
+        }
+    };
+
+    let final_round = match game.final_round.as_mut() {
+        Some(x) => x,
+        None => return Err(GameError::InvalidGameState),
+    };
+
+    let answer = final_round
+        .answers
+        .get(&username)
+        .cloned()
+        .unwrap_or_default();
+    let wager = final_round.wagers.get(&username).cloned().unwrap_or(0);
+    final_round.revealed.push(username.clone());
+
+    game.state = GameState::FinalRoundReveal {
+        question: final_round.question.clone(),
+        username: username.clone(),
+        answer: answer.clone(),
+        wager,
+    };
+    game.log.push(GameLog::FinalAnswerRevealed {
+        time: get_time(),
+        username,
+        answer,
+        wager,
+    });
+
+    return Ok(());
+}
+
+fn create_team(
+    game_entry: &mut RefMut<String, GameEntry>,
+    role: PlayerRole,
+    name: String,
+) -> Result<(), GameError> {
+    if role != PlayerRole::Host {
+        return Err(GameError::InsufficientPermissions);
+    }
+
+    if game_entry.game.state != GameState::WaitingToStart {
+        return Err(GameError::InvalidGameState);
+    }
+
+    let game = &mut game_entry.game;
+    let team_id = (1..)
+        .map(|x| format!("team-{x}"))
+        .find(|id| !game.teams.iter().any(|x| &x.team_id == id))
+        .unwrap_or_default();
+    game.teams.push(Team {
+        team_id,
+        name,
+        score: 0,
+    });
+    game.log.push(GameLog::TeamsUpdated { time: get_time() });
+
+    return Ok(());
+}
+
+fn rename_team(
+    game_entry: &mut RefMut<String, GameEntry>,
+    role: PlayerRole,
+    team_id: String,
+    name: String,
+) -> Result<(), GameError> {
+    if role != PlayerRole::Host {
+        return Err(GameError::InsufficientPermissions);
+    }
+
+    if game_entry.game.state != GameState::WaitingToStart {
+        return Err(GameError::InvalidGameState);
+    }
+
+    let game = &mut game_entry.game;
+    match game.teams.iter_mut().find(|x| x.team_id == team_id) {
+        Some(team) => team.name = name,
+        None => return Err(GameError::TeamNotFound),
+    }
+    game.log.push(GameLog::TeamsUpdated { time: get_time() });
+
+    return Ok(());
+}
+
+fn remove_team(
+    game_entry: &mut RefMut<String, GameEntry>,
+    role: PlayerRole,
+    team_id: String,
+) -> Result<(), GameError> {
+    if role != PlayerRole::Host {
+        return Err(GameError::InsufficientPermissions);
+    }
+
+    if game_entry.game.state != GameState::WaitingToStart {
+        return Err(GameError::InvalidGameState);
+    }
+
+    let game = &mut game_entry.game;
+    let index = match game.teams.iter().position(|x| x.team_id == team_id) {
+        Some(x) => x,
+        None => return Err(GameError::TeamNotFound),
+    };
+    game.teams.remove(index);
+
+    // Members of the removed team go to whichever team is smallest
+    for index in 0..game.players.len() {
+        if game.players[index].team_id.as_ref() == Some(&team_id) {
+            game.players[index].team_id = get_smallest_team(game);
+        }
+    }
+    game.log.push(GameLog::TeamsUpdated { time: get_time() });
+
+    return Ok(());
+}
+
+fn assign_team(
+    game_entry: &mut RefMut<String, GameEntry>,
+    role: PlayerRole,
+    update_username: String,
+    team_id: Option<String>,
+) -> Result<(), GameError> {
+    if role != PlayerRole::Host {
+        return Err(GameError::InsufficientPermissions);
+    }
+
+    if game_entry.game.state != GameState::WaitingToStart {
+        return Err(GameError::InvalidGameState);
+    }
+
+    let game = &mut game_entry.game;
+    if let Some(team_id) = &team_id {
+        if !game.teams.iter().any(|x| &x.team_id == team_id) {
+            return Err(GameError::TeamNotFound);
+        }
+    }
+
+    match get_player(game, update_username) {
+        Some(player) if player.role == PlayerRole::Contestant => player.team_id = team_id,
+        Some(_) => return Err(GameError::InvalidGameState),
+        None => return Err(GameError::PlayerNotFound),
+    }
+    game.log.push(GameLog::TeamsUpdated { time: get_time() });
+
+    return Ok(());
+}
+
+/// Shuffle all contestants evenly between the existing teams.
+fn balance_teams(
+    game_entry: &mut RefMut<String, GameEntry>,
+    role: PlayerRole,
+) -> Result<(), GameError> {
+    if role != PlayerRole::Host {
+        return Err(GameError::InsufficientPermissions);
+    }
+
+    if game_entry.game.state != GameState::WaitingToStart {
+        return Err(GameError::InvalidGameState);
+    }
+
+    let game = &mut game_entry.game;
+    if game.teams.is_empty() {
+        return Err(GameError::TeamNotFound);
+    }
+
+    let mut contestants: Vec<&mut Player> = game
+        .players
+        .iter_mut()
+        .filter(|x| x.role == PlayerRole::Contestant)
+        .collect();
+    contestants.shuffle(&mut rand::thread_rng());
+    for (index, player) in contestants.into_iter().enumerate() {
+        player.team_id = Some(game.teams[index % game.teams.len()].team_id.clone());
+    }
+    game.log.push(GameLog::TeamsUpdated { time: get_time() });
 
-    match player_to_update {
+    return Ok(());
+}
+
+/// Add points to a player, and to their team when playing in teams.
+fn award_points(game: &mut Game, username: String, points: isize) -> Result<(), GameError> {
+    let player = match get_player(game, username) {
+        Some(x) => x,
         None => return Err(GameError::PlayerNotFound),
-        Some(player) => {
-            let old_score = player.score;
-            player.score = new_score;
-            game_entry.game.log.push(GameLog::ManualScoreUpdated {
-                time: get_time(),
-                username: update_username,
-                old_score: old_score,
-                new_score: new_score,
-            })
+    };
+    player.score += points;
+
+    if let Some(team_id) = player.team_id.clone() {
+        if let Some(team) = game.teams.iter_mut().find(|x| x.team_id == team_id) {
+            team.score += points;
         }
     }
 
     return Ok(());
 }
 
+/// The score that counts for a player, which is their team's score when playing in teams.
+fn get_score(game: &Game, username: &str) -> Option<isize> {
+    let player = game.players.iter().find(|x| x.username == username)?;
+    return match &player.team_id {
+        Some(team_id) => game
+            .teams
+            .iter()
+            .find(|x| &x.team_id == team_id)
+            .map(|x| x.score),
+        None => Some(player.score),
+    };
+}
+
+/// The player whose final round wager counts for `username`, which is whichever teammate made the
+/// team's wager when playing in teams.
+fn get_final_round_entrant(game: &Game, username: &str) -> Option<String> {
+    let final_round = game.final_round.as_ref()?;
+    let team_id = get_team_id(game, username);
+    return final_round
+        .wagers
+        .keys()
+        .find(|x| *x == username || (team_id.is_some() && get_team_id(game, x) == team_id))
+        .cloned();
+}
+
+fn get_team_id(game: &Game, username: &str) -> Option<String> {
+    return game
+        .players
+        .iter()
+        .find(|x| x.username == username)
+        .and_then(|x| x.team_id.clone());
+}
+
+fn get_smallest_team(game: &Game) -> Option<String> {
+    return game
+        .teams
+        .iter()
+        .min_by_key(|team| {
+            game.players
+                .iter()
+                .filter(|x| x.team_id.as_ref() == Some(&team.team_id))
+                .count()
+        })
+        .map(|x| x.team_id.clone());
+}
+
+/// Start a countdown for the current state, or clear any existing timer if there is no limit.
+fn start_countdown(game_entry: &mut GameEntry, kind: TimerKind, limit_secs: Option<u64>) {
+    let timer = limit_secs.map(|x| GameTimer {
+        kind,
+        deadline: get_time().saturating_add(x.saturating_mul(1000)),
+    });
+    game_entry.set_timer(timer);
+}
+
 fn get_player(game: &mut Game, username: String) -> Option<&mut Player> {
     return game.players.iter_mut().find(|x| x.username == username);
 }
 
+/// Check the questions for a game are complete, filling in the choices for true or false questions
+/// and clearing anything that should only be filled in by playing the final round.
+pub fn prepare_questions(
+    rounds: &mut [Vec<Category>],
+    final_round: &mut Option<FinalRound>,
+) -> Result<(), GameError> {
+    if rounds.is_empty()
+        || rounds.iter().any(|x| x.is_empty())
+        || rounds.iter().flatten().any(|x| x.questions.is_empty())
+        || rounds
+            .iter()
+            .flatten()
+            .flat_map(|x| x.questions.iter())
+            .any(|x| !x.is_complete())
+        || final_round
+            .as_ref()
+            .is_some_and(|x| !x.question.is_complete())
+    {
+        return Err(GameError::MissingQuestions);
+    }
+
+    let questions = rounds
+        .iter_mut()
+        .flatten()
+        .flat_map(|x| x.questions.iter_mut())
+        .chain(final_round.iter_mut().map(|x| &mut x.question));
+    for question in questions {
+        if question.kind == QuestionKind::TrueFalse && question.choices.is_empty() {
+            question.choices = vec!["True".to_string(), "False".to_string()];
+        }
+        if !has_valid_choices(question) {
+            return Err(GameError::InvalidQuestionChoices);
+        }
+    }
+
+    if let Some(final_round) = final_round {
+        final_round.wagers.clear();
+        final_round.answers.clear();
+        final_round.revealed.clear();
+    }
+    return Ok(());
+}
+
+/// Free response questions don't need any choices. Otherwise there must be at least 2 distinct
+/// choices, one of which is the correct answer.
+fn has_valid_choices(question: &Question) -> bool {
+    if question.kind == QuestionKind::FreeResponse {
+        return true;
+    }
+
+    let mut unique: Vec<&String> = question.choices.iter().collect();
+    unique.sort();
+    unique.dedup();
+    return unique.len() == question.choices.len()
+        && unique.len() >= 2
+        && question.choices.contains(&question.correct_answer);
+}
+
 fn get_question(game: &mut Game, question_id: String) -> Option<&mut Question> {
     return game
         .rounds
@@ -393,14 +1721,16 @@ fn mark_question_answered(
     question.answered = true;
 
     // Inform all clients that this question has been answered
-    let _ = game_entry.sender.send(GameMessage::QuestionUpdate {
-        question: question.clone(),
-    });
+    let question = question.clone();
+    game_entry.broadcast(GameMessage::QuestionUpdate { question });
+
+    game_entry.buzzes.clear();
+    game_entry.set_timer(None);
 
     let game = &mut game_entry.game;
     game.state = GameState::PickAQuestion;
 
-    // If all questions are answered, then end the game
+    // If all questions are answered, then end the game, or move to the final round if there is one
     if game
         .rounds
         .iter()
@@ -408,7 +1738,12 @@ fn mark_question_answered(
         .flat_map(|x| x.questions.iter())
         .all(|x| x.answered)
     {
-        game.state = GameState::Finished;
+        game.state = match &game.final_round {
+            Some(final_round) => GameState::FinalRoundWager {
+                category: final_round.name.clone(),
+            },
+            None => GameState::Finished,
+        };
         return Ok(());
     }
 
@@ -423,3 +1758,1095 @@ fn mark_question_answered(
 
     return Ok(());
 }
+
+#[cfg(test)]
+mod tests {
+    use dashmap::DashMap;
+
+    use std::{collections::HashMap, sync::Arc};
+
+    use super::*;
+    use crate::{
+        boards::MemoryBoardStore, config::ServerConfig, models::GameSettings,
+        store::MemoryGameStore,
+    };
+
+    const GAME_ID: &str = "TEST";
+    const HOST: &str = "host-token";
+    const ALICE: &str = "alice-token";
+    const BOB: &str = "bob-token";
+
+    fn question(question_id: &str, value: isize) -> Question {
+        return Question {
+            question_id: question_id.to_string(),
+            detail: format!("Question {question_id}"),
+            correct_answer: "The Answer".to_string(),
+            value,
+            answered: false,
+            daily_double: false,
+            alternate_answers: Vec::new(),
+            kind: QuestionKind::FreeResponse,
+            choices: Vec::new(),
+            tags: Vec::new(),
+        };
+    }
+
+    /// Settings where the first buzz wins straight away, so tests don't need the timer task.
+    fn settings() -> GameSettings {
+        return GameSettings {
+            buzz_window_ms: 0,
+            ..GameSettings::default()
+        };
+    }
+
+    /// A started game with a Host and two contestants, alice and bob, and a single category.
+    fn new_game(settings: GameSettings, questions: Vec<Question>) -> DashMap<String, GameEntry> {
+        let mut game: Game = CreateGameRequest {
+            rounds: vec![vec![Category {
+                category_id: "c1".to_string(),
+                name: "Category".to_string(),
+                questions,
+            }]],
+            final_round: None,
+            settings: Some(settings),
+            board_id: None,
+        }
+        .into();
+        game.id = GAME_ID.to_string();
+        game.state = GameState::PickAQuestion;
+
+        let mut game_entry = GameEntry::new(game, 16);
+        for (token, username, role) in [
+            (HOST, "host", PlayerRole::Host),
+            (ALICE, "alice", PlayerRole::Contestant),
+            (BOB, "bob", PlayerRole::Contestant),
+        ] {
+            game_entry.game.players.push(Player {
+                username: username.to_string(),
+                score: 0,
+                role,
+                team_id: None,
+            });
+            game_entry
+                .auth
+                .player_tokens
+                .insert(token.to_string(), username.to_string());
+        }
+
+        let games = DashMap::new();
+        games.insert(GAME_ID.to_string(), game_entry);
+        return games;
+    }
+
+    fn send(
+        games: &DashMap<String, GameEntry>,
+        token: &str,
+        request: UpdateGameRequest,
+    ) -> Result<(), GameError> {
+        let mut game_entry = games.get_mut(GAME_ID).unwrap();
+        return handle_game_request(&mut game_entry, token.to_string(), request);
+    }
+
+    fn state(games: &DashMap<String, GameEntry>) -> GameState {
+        return games.get(GAME_ID).unwrap().game.state.clone();
+    }
+
+    fn score(games: &DashMap<String, GameEntry>, username: &str) -> isize {
+        let game_entry = games.get(GAME_ID).unwrap();
+        return game_entry
+            .game
+            .players
+            .iter()
+            .find(|x| x.username == username)
+            .unwrap()
+            .score;
+    }
+
+    /// Pick a question and let `token`'s player buzz in.
+    fn buzz_in(games: &DashMap<String, GameEntry>, question_id: &str, token: &str) {
+        let question_id = question_id.to_string();
+        send(games, HOST, UpdateGameRequest::PickQuestion { question_id }).unwrap();
+        send(games, HOST, UpdateGameRequest::AllowAnswering).unwrap();
+        send(games, token, UpdateGameRequest::AnswerQuestion).unwrap();
+        assert!(matches!(state(games), GameState::CheckAnswer { .. }));
+    }
+
+    /// Server state holding `games`, kept in memory only.
+    fn app_state(games: DashMap<String, GameEntry>) -> AppState {
+        let mut state = AppState::new(
+            ServerConfig::default(),
+            Arc::new(MemoryGameStore),
+            Arc::new(MemoryBoardStore),
+        );
+        state.games = Arc::new(games);
+        return state;
+    }
+
+    #[test]
+    fn only_hosts_can_confirm_answers() {
+        let games = new_game(settings(), vec![question("q1", 100)]);
+        buzz_in(&games, "q1", ALICE);
+
+        let result = send(
+            &games,
+            ALICE,
+            UpdateGameRequest::ConfirmAnswer { is_correct: true },
+        );
+        assert!(matches!(result, Err(GameError::InsufficientPermissions)));
+        assert_eq!(score(&games, "alice"), 0);
+
+        send(
+            &games,
+            HOST,
+            UpdateGameRequest::ConfirmAnswer { is_correct: true },
+        )
+        .unwrap();
+        assert_eq!(score(&games, "alice"), 100);
+    }
+
+    #[test]
+    fn unwagered_daily_double_stays_open_after_a_wrong_answer() {
+        let mut daily_double = question("q1", 100);
+        daily_double.daily_double = true;
+        let games = new_game(settings(), vec![daily_double, question("q2", 200)]);
+
+        // Nobody has won a question yet, so the daily double is played like any other question
+        buzz_in(&games, "q1", ALICE);
+        send(
+            &games,
+            HOST,
+            UpdateGameRequest::ConfirmAnswer { is_correct: false },
+        )
+        .unwrap();
+
+        assert!(matches!(state(&games), GameState::WaitingForAnswer { .. }));
+        send(&games, BOB, UpdateGameRequest::AnswerQuestion).unwrap();
+        assert!(matches!(
+            state(&games),
+            GameState::CheckAnswer { player, .. } if player.username == "bob"
+        ));
+    }
+
+    #[test]
+    fn wagered_daily_double_closes_after_a_wrong_answer() {
+        let mut daily_double = question("q2", 200);
+        daily_double.daily_double = true;
+        let games = new_game(settings(), vec![question("q1", 100), daily_double]);
+        buzz_in(&games, "q1", ALICE);
+        send(
+            &games,
+            HOST,
+            UpdateGameRequest::ConfirmAnswer { is_correct: true },
+        )
+        .unwrap();
+
+        let question_id = "q2".to_string();
+        send(
+            &games,
+            HOST,
+            UpdateGameRequest::PickQuestion { question_id },
+        )
+        .unwrap();
+        assert!(matches!(state(&games), GameState::DailyDoubleWager { .. }));
+        send(&games, ALICE, UpdateGameRequest::SubmitWager { amount: 50 }).unwrap();
+        send(
+            &games,
+            HOST,
+            UpdateGameRequest::ConfirmAnswer { is_correct: false },
+        )
+        .unwrap();
+
+        assert_eq!(score(&games, "alice"), 50);
+        assert_eq!(state(&games), GameState::Finished);
+    }
+
+    #[test]
+    fn time_limits_must_be_in_range() {
+        assert!(settings().has_valid_limits());
+        for (buzz_window_ms, time_limit_secs) in [(0, Some(0)), (0, Some(u64::MAX)), (60_000, None)]
+        {
+            let settings = GameSettings {
+                buzz_window_ms,
+                answer_time_limit_secs: time_limit_secs,
+                ..GameSettings::default()
+            };
+            assert!(!settings.has_valid_limits());
+        }
+    }
+
+    #[test]
+    fn huge_time_limits_do_not_overflow() {
+        // Games saved before time limits were checked could still have any limit
+        let settings = GameSettings {
+            buzz_time_limit_secs: Some(u64::MAX),
+            answer_time_limit_secs: Some(u64::MAX),
+            ..settings()
+        };
+        let games = new_game(settings, vec![question("q1", 100)]);
+        buzz_in(&games, "q1", ALICE);
+
+        let game_entry = games.get(GAME_ID).unwrap();
+        assert_eq!(game_entry.game.timer.as_ref().unwrap().deadline, u64::MAX);
+    }
+
+    #[test]
+    fn suggested_answers_stop_the_answer_countdown() {
+        let settings = GameSettings {
+            answer_time_limit_secs: Some(10),
+            answer_mode: AnswerMode::Suggest,
+            ..settings()
+        };
+        let games = new_game(settings, vec![question("q1", 100)]);
+        buzz_in(&games, "q1", ALICE);
+        let timer = games.get(GAME_ID).unwrap().game.timer.clone().unwrap();
+
+        let answer = "the answer".to_string();
+        send(&games, ALICE, UpdateGameRequest::SubmitAnswer { answer }).unwrap();
+        let result = handle_timer(&mut games.get_mut(GAME_ID).unwrap(), timer);
+
+        assert!(matches!(result, Err(GameError::InvalidGameState)));
+        assert!(matches!(
+            state(&games),
+            GameState::CheckAnswer {
+                submitted_answer: Some(SubmittedAnswer {
+                    suggested_correct: true,
+                    ..
+                }),
+                ..
+            }
+        ));
+        assert_eq!(score(&games, "alice"), 0);
+    }
+
+    #[test]
+    fn undo_restores_the_last_host_action() {
+        let games = new_game(settings(), vec![question("q1", 100)]);
+        buzz_in(&games, "q1", ALICE);
+        send(
+            &games,
+            HOST,
+            UpdateGameRequest::ConfirmAnswer { is_correct: false },
+        )
+        .unwrap();
+        assert_eq!(score(&games, "alice"), -100);
+
+        send(&games, HOST, UpdateGameRequest::UndoLastAction).unwrap();
+        assert_eq!(score(&games, "alice"), 0);
+        assert!(matches!(
+            state(&games),
+            GameState::CheckAnswer { player, .. } if player.username == "alice"
+        ));
+    }
+
+    #[test]
+    fn undo_skips_over_contestant_actions() {
+        let games = new_game(settings(), vec![question("q1", 100)]);
+        buzz_in(&games, "q1", ALICE);
+
+        // The buzz isn't an action of its own, so the Host's allowing answering is undone instead
+        send(&games, HOST, UpdateGameRequest::UndoLastAction).unwrap();
+        assert!(matches!(state(&games), GameState::ReadQuestion { .. }));
+
+        let result = send(&games, ALICE, UpdateGameRequest::UndoLastAction);
+        assert!(matches!(result, Err(GameError::InsufficientPermissions)));
+    }
+
+    #[test]
+    fn kicking_the_answering_contestant_while_paused() {
+        let settings = GameSettings {
+            buzz_time_limit_secs: Some(10),
+            answer_time_limit_secs: Some(10),
+            ..settings()
+        };
+        let games = new_game(settings, vec![question("q1", 100)]);
+        buzz_in(&games, "q1", ALICE);
+
+        send(&games, HOST, UpdateGameRequest::PauseGame).unwrap();
+        let kick_username = "alice".to_string();
+        send(
+            &games,
+            HOST,
+            UpdateGameRequest::KickPlayer { kick_username },
+        )
+        .unwrap();
+        assert!(matches!(
+            state(&games),
+            GameState::Paused {
+                timer: Some(PausedTimer {
+                    kind: TimerKind::BuzzTimeout,
+                    remaining_ms: 10_000,
+                }),
+                ..
+            }
+        ));
+
+        // Once resumed the others get to buzz in, instead of waiting on alice forever
+        send(&games, HOST, UpdateGameRequest::ResumeGame).unwrap();
+        assert!(matches!(state(&games), GameState::WaitingForAnswer { .. }));
+        send(&games, BOB, UpdateGameRequest::AnswerQuestion).unwrap();
+        assert!(matches!(
+            state(&games),
+            GameState::CheckAnswer { player, .. } if player.username == "bob"
+        ));
+    }
+
+    #[test]
+    fn demoting_the_wagering_contestant_while_paused() {
+        let mut daily_double = question("q2", 200);
+        daily_double.daily_double = true;
+        let games = new_game(settings(), vec![question("q1", 100), daily_double]);
+        buzz_in(&games, "q1", ALICE);
+        send(
+            &games,
+            HOST,
+            UpdateGameRequest::ConfirmAnswer { is_correct: true },
+        )
+        .unwrap();
+        let question_id = "q2".to_string();
+        send(
+            &games,
+            HOST,
+            UpdateGameRequest::PickQuestion { question_id },
+        )
+        .unwrap();
+
+        send(&games, HOST, UpdateGameRequest::PauseGame).unwrap();
+        let demote_username = "alice".to_string();
+        send(
+            &games,
+            HOST,
+            UpdateGameRequest::DemotePlayer { demote_username },
+        )
+        .unwrap();
+        send(&games, HOST, UpdateGameRequest::ResumeGame).unwrap();
+
+        assert_eq!(state(&games), GameState::PickAQuestion);
+        assert_eq!(games.get(GAME_ID).unwrap().game.timer, None);
+    }
+
+    #[test]
+    fn host_token_acts_as_the_first_host_to_join() {
+        let state = app_state(new_game(settings(), vec![question("q1", 100)]));
+        {
+            let mut game_entry = state.games.get_mut(GAME_ID).unwrap();
+            game_entry.auth.host_token = "host-secret".to_string();
+            game_entry.auth.player_tokens.remove(HOST);
+            game_entry
+                .game
+                .players
+                .retain(|x| x.role != PlayerRole::Host);
+        }
+
+        let join = |username: &str| {
+            let token = Some("host-secret".to_string());
+            let username = username.to_string();
+            return state
+                .clone()
+                .join_game(GAME_ID.to_string(), username, PlayerRole::Host, token, None)
+                .unwrap();
+        };
+        let creator_token = join("creator");
+        for username in ["cohost-1", "cohost-2", "cohost-3"] {
+            join(username);
+        }
+
+        for _ in 0..10 {
+            let game_entry = state.games.get(GAME_ID).unwrap();
+            assert_eq!(game_entry.host_player_token(), Some(&creator_token));
+        }
+    }
+
+    #[test]
+    fn new_final_rounds_start_without_wagers_or_answers() {
+        let mut rounds = vec![vec![Category {
+            category_id: "c1".to_string(),
+            name: "Category".to_string(),
+            questions: vec![question("q1", 100)],
+        }]];
+        let mut final_round = Some(FinalRound {
+            name: "Final".to_string(),
+            question: question("final", 0),
+            wagers: [("alice".to_string(), 1000)].into(),
+            answers: [("alice".to_string(), "The Answer".to_string())].into(),
+            revealed: vec!["alice".to_string()],
+        });
+
+        prepare_questions(&mut rounds, &mut final_round).unwrap();
+        let final_round = final_round.unwrap();
+        assert!(final_round.wagers.is_empty());
+        assert!(final_round.answers.is_empty());
+        assert!(final_round.revealed.is_empty());
+    }
+
+    #[test]
+    fn only_hosts_can_pick_questions() {
+        let games = new_game(settings(), vec![question("q1", 100)]);
+        let question_id = "q1".to_string();
+
+        let result = send(
+            &games,
+            ALICE,
+            UpdateGameRequest::PickQuestion { question_id },
+        );
+        assert!(matches!(result, Err(GameError::InsufficientPermissions)));
+        assert_eq!(state(&games), GameState::PickAQuestion);
+    }
+
+    /// Play q1 so alice is on 300 and bob is on 100, reaching the final round.
+    fn reach_final_round() -> DashMap<String, GameEntry> {
+        let games = new_game(settings(), vec![question("q1", 300)]);
+        {
+            let mut game_entry = games.get_mut(GAME_ID).unwrap();
+            game_entry.game.final_round = Some(FinalRound {
+                name: "Final".to_string(),
+                question: question("final", 0),
+                wagers: HashMap::new(),
+                answers: HashMap::new(),
+                revealed: Vec::new(),
+            });
+            award_points(&mut game_entry.game, "bob".to_string(), 100).unwrap();
+        }
+        buzz_in(&games, "q1", ALICE);
+        send(
+            &games,
+            HOST,
+            UpdateGameRequest::ConfirmAnswer { is_correct: true },
+        )
+        .unwrap();
+        assert!(matches!(state(&games), GameState::FinalRoundWager { .. }));
+        return games;
+    }
+
+    #[test]
+    fn final_round_wagers_are_limited_to_the_score() {
+        let games = reach_final_round();
+
+        let result = send(&games, BOB, UpdateGameRequest::SubmitWager { amount: 101 });
+        assert!(matches!(result, Err(GameError::InvalidWager)));
+        let result = send(&games, BOB, UpdateGameRequest::SubmitWager { amount: -1 });
+        assert!(matches!(result, Err(GameError::InvalidWager)));
+        let result = send(&games, HOST, UpdateGameRequest::SubmitWager { amount: 0 });
+        assert!(matches!(result, Err(GameError::InsufficientPermissions)));
+
+        send(
+            &games,
+            ALICE,
+            UpdateGameRequest::SubmitWager { amount: 300 },
+        )
+        .unwrap();
+        let result = send(&games, HOST, UpdateGameRequest::ShowFinalQuestion);
+        assert!(matches!(result, Err(GameError::MissingWagers)));
+    }
+
+    #[test]
+    fn final_round_answers_are_revealed_from_the_lowest_score() {
+        let games = reach_final_round();
+        send(
+            &games,
+            ALICE,
+            UpdateGameRequest::SubmitWager { amount: 200 },
+        )
+        .unwrap();
+        send(&games, BOB, UpdateGameRequest::SubmitWager { amount: 100 }).unwrap();
+        send(&games, HOST, UpdateGameRequest::ShowFinalQuestion).unwrap();
+        for (token, answer) in [(ALICE, "Wrong"), (BOB, "The Answer")] {
+            let answer = answer.to_string();
+            send(
+                &games,
+                token,
+                UpdateGameRequest::SubmitFinalAnswer { answer },
+            )
+            .unwrap();
+        }
+
+        send(&games, HOST, UpdateGameRequest::RevealFinalAnswer).unwrap();
+        assert!(matches!(
+            state(&games),
+            GameState::FinalRoundReveal { username, .. } if username == "bob"
+        ));
+        let score_answer = |is_correct| UpdateGameRequest::ScoreFinalAnswer { is_correct };
+        send(&games, HOST, score_answer(true)).unwrap();
+        assert!(matches!(
+            state(&games),
+            GameState::FinalRoundReveal { username, .. } if username == "alice"
+        ));
+        send(&games, HOST, score_answer(false)).unwrap();
+
+        assert_eq!(state(&games), GameState::Finished);
+        assert_eq!(score(&games, "alice"), 100);
+        assert_eq!(score(&games, "bob"), 200);
+    }
+
+    #[test]
+    fn buzzes_are_compensated_for_latency() {
+        let settings = GameSettings {
+            buzz_window_ms: 200,
+            latency_compensation: true,
+            ..GameSettings::default()
+        };
+        let games = new_game(settings, vec![question("q1", 100)]);
+        games
+            .get_mut(GAME_ID)
+            .unwrap()
+            .latencies
+            .extend([("alice".to_string(), 0), ("bob".to_string(), 150)]);
+        let question_id = "q1".to_string();
+        send(
+            &games,
+            HOST,
+            UpdateGameRequest::PickQuestion { question_id },
+        )
+        .unwrap();
+        send(&games, HOST, UpdateGameRequest::AllowAnswering).unwrap();
+
+        // Bob's buzz arrives second, but was sent first once their slower connection is allowed for
+        send(&games, ALICE, UpdateGameRequest::AnswerQuestion).unwrap();
+        send(&games, BOB, UpdateGameRequest::AnswerQuestion).unwrap();
+        assert!(matches!(state(&games), GameState::WaitingForAnswer { .. }));
+
+        resolve_buzzes(&mut games.get_mut(GAME_ID).unwrap()).unwrap();
+        assert!(matches!(
+            state(&games),
+            GameState::CheckAnswer { player, .. } if player.username == "bob"
+        ));
+    }
+
+    #[test]
+    fn latency_compensation_is_limited_to_the_buzz_window() {
+        let settings = GameSettings {
+            buzz_window_ms: 200,
+            latency_compensation: true,
+            ..GameSettings::default()
+        };
+        let games = new_game(settings, vec![question("q1", 100)]);
+        games
+            .get_mut(GAME_ID)
+            .unwrap()
+            .latencies
+            .insert("alice".to_string(), 10_000);
+        let question_id = "q1".to_string();
+        send(
+            &games,
+            HOST,
+            UpdateGameRequest::PickQuestion { question_id },
+        )
+        .unwrap();
+        send(&games, HOST, UpdateGameRequest::AllowAnswering).unwrap();
+        send(&games, ALICE, UpdateGameRequest::AnswerQuestion).unwrap();
+
+        let game_entry = games.get(GAME_ID).unwrap();
+        assert_eq!(game_entry.buzzes[0].latency_compensation, 200);
+    }
+
+    /// Put alice and bob on the same team before the game starts.
+    fn same_team(games: &DashMap<String, GameEntry>) {
+        games.get_mut(GAME_ID).unwrap().game.state = GameState::WaitingToStart;
+        let name = "Team".to_string();
+        send(games, HOST, UpdateGameRequest::CreateTeam { name }).unwrap();
+        for update_username in ["alice", "bob"] {
+            let request = UpdateGameRequest::AssignTeam {
+                update_username: update_username.to_string(),
+                team_id: Some("team-1".to_string()),
+            };
+            send(games, HOST, request).unwrap();
+        }
+        games.get_mut(GAME_ID).unwrap().game.state = GameState::PickAQuestion;
+    }
+
+    #[test]
+    fn only_the_first_buzz_from_each_team_counts() {
+        let settings = GameSettings {
+            buzz_window_ms: 200,
+            ..GameSettings::default()
+        };
+        let games = new_game(settings, vec![question("q1", 100)]);
+        same_team(&games);
+        let question_id = "q1".to_string();
+        send(
+            &games,
+            HOST,
+            UpdateGameRequest::PickQuestion { question_id },
+        )
+        .unwrap();
+        send(&games, HOST, UpdateGameRequest::AllowAnswering).unwrap();
+
+        send(&games, ALICE, UpdateGameRequest::AnswerQuestion).unwrap();
+        send(&games, BOB, UpdateGameRequest::AnswerQuestion).unwrap();
+        assert_eq!(games.get(GAME_ID).unwrap().buzzes.len(), 1);
+    }
+
+    #[test]
+    fn points_go_to_the_team() {
+        let games = new_game(settings(), vec![question("q1", 100)]);
+        same_team(&games);
+        buzz_in(&games, "q1", ALICE);
+        send(
+            &games,
+            HOST,
+            UpdateGameRequest::ConfirmAnswer { is_correct: true },
+        )
+        .unwrap();
+
+        let game_entry = games.get(GAME_ID).unwrap();
+        assert_eq!(game_entry.game.teams[0].score, 100);
+        assert_eq!(get_score(&game_entry.game, "bob"), Some(100));
+        assert_eq!(get_score(&game_entry.game, "alice"), Some(100));
+    }
+
+    #[test]
+    fn teams_can_only_change_before_the_game_starts() {
+        let games = new_game(settings(), vec![question("q1", 100)]);
+        let name = "Team".to_string();
+        let result = send(&games, HOST, UpdateGameRequest::CreateTeam { name });
+        assert!(matches!(result, Err(GameError::InvalidGameState)));
+    }
+
+    fn multiple_choice(question_id: &str, value: isize) -> Question {
+        return Question {
+            kind: QuestionKind::MultipleChoice,
+            choices: vec!["Wrong".to_string(), "The Answer".to_string()],
+            ..question(question_id, value)
+        };
+    }
+
+    /// Where `choice` ended up in the question being answered, as choices are shuffled when picked.
+    fn choice_index(games: &DashMap<String, GameEntry>, choice: &str) -> usize {
+        return match state(games) {
+            GameState::CheckAnswer { question, .. } => {
+                question.choices.iter().position(|x| x == choice).unwrap()
+            }
+            _ => panic!("expected a player to be answering"),
+        };
+    }
+
+    #[test]
+    fn choices_are_graded_by_the_server() {
+        let games = new_game(
+            settings(),
+            vec![multiple_choice("q1", 100), multiple_choice("q2", 200)],
+        );
+        buzz_in(&games, "q1", ALICE);
+        let index = choice_index(&games, "The Answer");
+        let result = send(&games, BOB, UpdateGameRequest::SubmitChoice { index });
+        assert!(matches!(result, Err(GameError::InsufficientPermissions)));
+        let result = send(&games, ALICE, UpdateGameRequest::SubmitChoice { index: 2 });
+        assert!(matches!(result, Err(GameError::ChoiceNotFound)));
+
+        send(&games, ALICE, UpdateGameRequest::SubmitChoice { index }).unwrap();
+        assert_eq!(score(&games, "alice"), 100);
+
+        buzz_in(&games, "q2", BOB);
+        let index = choice_index(&games, "Wrong");
+        send(&games, BOB, UpdateGameRequest::SubmitChoice { index }).unwrap();
+        assert_eq!(score(&games, "bob"), -200);
+    }
+
+    #[test]
+    fn true_or_false_questions_get_their_choices() {
+        let mut rounds = vec![vec![Category {
+            category_id: "c1".to_string(),
+            name: "Category".to_string(),
+            questions: vec![Question {
+                kind: QuestionKind::TrueFalse,
+                correct_answer: "False".to_string(),
+                ..question("q1", 100)
+            }],
+        }]];
+        prepare_questions(&mut rounds, &mut None).unwrap();
+        assert_eq!(rounds[0][0].questions[0].choices, ["True", "False"]);
+    }
+
+    #[test]
+    fn choices_must_include_the_answer_once() {
+        for choices in [
+            vec!["The Answer"],
+            vec!["Wrong", "Also Wrong"],
+            vec!["The Answer", "The Answer"],
+        ] {
+            let mut rounds = vec![vec![Category {
+                category_id: "c1".to_string(),
+                name: "Category".to_string(),
+                questions: vec![Question {
+                    choices: choices.iter().map(|x| x.to_string()).collect(),
+                    ..multiple_choice("q1", 100)
+                }],
+            }]];
+            let result = prepare_questions(&mut rounds, &mut None);
+            assert!(matches!(result, Err(GameError::InvalidQuestionChoices)));
+        }
+    }
+
+    #[test]
+    fn banned_players_cannot_join_again() {
+        let state = app_state(new_game(settings(), vec![question("q1", 100)]));
+        let join = |username: &str, role: PlayerRole| {
+            return state.clone().join_game(
+                GAME_ID.to_string(),
+                username.to_string(),
+                role,
+                None,
+                None,
+            );
+        };
+        let kick_username = "alice".to_string();
+        send(
+            &state.games,
+            HOST,
+            UpdateGameRequest::KickPlayer { kick_username },
+        )
+        .unwrap();
+        let ban_username = "bob".to_string();
+        send(
+            &state.games,
+            HOST,
+            UpdateGameRequest::BanPlayer { ban_username },
+        )
+        .unwrap();
+        let game_entry = state.games.get(GAME_ID).unwrap();
+        assert!(game_entry.auth.get_username(BOB).is_none());
+        drop(game_entry);
+
+        // Only spectators can join once the game has started, which is enough to tell them apart
+        assert!(join("alice", PlayerRole::Spectator).is_ok());
+        let result = join("bob", PlayerRole::Spectator);
+        assert!(matches!(result, Err(GameError::PlayerBanned)));
+    }
+
+    #[test]
+    fn hosts_cannot_be_kicked() {
+        let games = new_game(settings(), vec![question("q1", 100)]);
+        let kick_username = "host".to_string();
+        let result = send(
+            &games,
+            HOST,
+            UpdateGameRequest::KickPlayer { kick_username },
+        );
+        assert!(matches!(result, Err(GameError::InsufficientPermissions)));
+        let kick_username = "bob".to_string();
+        let result = send(
+            &games,
+            ALICE,
+            UpdateGameRequest::KickPlayer { kick_username },
+        );
+        assert!(matches!(result, Err(GameError::InsufficientPermissions)));
+    }
+
+    #[test]
+    fn the_last_host_cannot_be_demoted() {
+        let games = new_game(settings(), vec![question("q1", 100)]);
+        let demote = |token: &str, username: &str| {
+            let demote_username = username.to_string();
+            return send(
+                &games,
+                token,
+                UpdateGameRequest::DemotePlayer { demote_username },
+            );
+        };
+        assert!(matches!(demote(HOST, "host"), Err(GameError::LastHost)));
+
+        let promote_username = "alice".to_string();
+        send(
+            &games,
+            HOST,
+            UpdateGameRequest::PromotePlayer { promote_username },
+        )
+        .unwrap();
+        demote(ALICE, "host").unwrap();
+        assert!(matches!(demote(ALICE, "alice"), Err(GameError::LastHost)));
+
+        let game_entry = games.get(GAME_ID).unwrap();
+        let roles: Vec<_> = game_entry.game.players.iter().map(|x| &x.role).collect();
+        assert_eq!(
+            roles,
+            [
+                &PlayerRole::Spectator,
+                &PlayerRole::Host,
+                &PlayerRole::Contestant
+            ]
+        );
+    }
+
+    #[test]
+    fn only_hosts_can_act_while_paused() {
+        let games = new_game(settings(), vec![question("q1", 100)]);
+        let question_id = "q1".to_string();
+        send(
+            &games,
+            HOST,
+            UpdateGameRequest::PickQuestion { question_id },
+        )
+        .unwrap();
+        send(&games, HOST, UpdateGameRequest::AllowAnswering).unwrap();
+        send(&games, HOST, UpdateGameRequest::PauseGame).unwrap();
+
+        let result = send(&games, ALICE, UpdateGameRequest::AnswerQuestion);
+        assert!(matches!(result, Err(GameError::GamePaused)));
+        let result = send(&games, ALICE, UpdateGameRequest::ResumeGame);
+        assert!(matches!(result, Err(GameError::GamePaused)));
+
+        send(&games, HOST, UpdateGameRequest::ResumeGame).unwrap();
+        send(&games, ALICE, UpdateGameRequest::AnswerQuestion).unwrap();
+        assert!(matches!(state(&games), GameState::CheckAnswer { .. }));
+    }
+
+    #[test]
+    fn countdowns_carry_on_from_where_they_were_paused() {
+        let settings = GameSettings {
+            answer_time_limit_secs: Some(10),
+            ..settings()
+        };
+        let games = new_game(settings, vec![question("q1", 100)]);
+        buzz_in(&games, "q1", ALICE);
+
+        send(&games, HOST, UpdateGameRequest::PauseGame).unwrap();
+        assert!(games.get(GAME_ID).unwrap().game.timer.is_none());
+        let remaining_ms = match state(&games) {
+            GameState::Paused {
+                timer: Some(timer), ..
+            } => timer.remaining_ms,
+            _ => panic!("expected the countdown to be paused"),
+        };
+        assert!(remaining_ms > 9_000 && remaining_ms <= 10_000);
+
+        send(&games, HOST, UpdateGameRequest::ResumeGame).unwrap();
+        let timer = games.get(GAME_ID).unwrap().game.timer.clone().unwrap();
+        assert_eq!(timer.kind, TimerKind::AnswerTimeout);
+        assert!(timer.deadline >= get_time() + remaining_ms - 1_000);
+        assert!(matches!(state(&games), GameState::CheckAnswer { .. }));
+    }
+
+    #[test]
+    fn joining_as_a_host_needs_the_host_token() {
+        let state = app_state(new_game(settings(), vec![question("q1", 100)]));
+        let host_token = state.games.get(GAME_ID).unwrap().auth.host_token.clone();
+        let join = |username: &str, token: Option<&str>| {
+            return state.clone().join_game(
+                GAME_ID.to_string(),
+                username.to_string(),
+                PlayerRole::Host,
+                token.map(|x| x.to_string()),
+                None,
+            );
+        };
+
+        let result = join("mallory", None);
+        assert!(matches!(result, Err(GameError::InvalidToken)));
+        let result = join("mallory", Some(ALICE));
+        assert!(matches!(result, Err(GameError::InvalidToken)));
+        assert!(join("cohost", Some(&host_token)).is_ok());
+    }
+
+    #[test]
+    fn rejoining_needs_the_players_token() {
+        let state = app_state(new_game(settings(), vec![question("q1", 100)]));
+        let host_token = state.games.get(GAME_ID).unwrap().auth.host_token.clone();
+        let join = |username: &str, role: PlayerRole, token: Option<&str>| {
+            return state.clone().join_game(
+                GAME_ID.to_string(),
+                username.to_string(),
+                role,
+                token.map(|x| x.to_string()),
+                None,
+            );
+        };
+
+        let result = join("alice", PlayerRole::Contestant, None);
+        assert!(matches!(result, Err(GameError::UsernameTaken)));
+        let result = join("alice", PlayerRole::Contestant, Some(BOB));
+        assert!(matches!(result, Err(GameError::InvalidToken)));
+        assert_eq!(
+            join("alice", PlayerRole::Contestant, Some(ALICE)).unwrap(),
+            ALICE
+        );
+
+        // The host token takes back a Host's place with a new token, but not a contestant's
+        let result = join("alice", PlayerRole::Contestant, Some(&host_token));
+        assert!(matches!(result, Err(GameError::InvalidToken)));
+        let token = join("host", PlayerRole::Host, Some(&host_token)).unwrap();
+        assert_ne!(token, HOST);
+        let game_entry = state.games.get(GAME_ID).unwrap();
+        assert_eq!(game_entry.auth.get_username(&token).unwrap(), "host");
+    }
+
+    #[test]
+    fn undo_keeps_role_changes() {
+        let games = new_game(settings(), vec![question("q1", 100)]);
+        buzz_in(&games, "q1", ALICE);
+        send(
+            &games,
+            HOST,
+            UpdateGameRequest::ConfirmAnswer { is_correct: true },
+        )
+        .unwrap();
+        let promote_username = "bob".to_string();
+        send(
+            &games,
+            HOST,
+            UpdateGameRequest::PromotePlayer { promote_username },
+        )
+        .unwrap();
+        let demote_username = "host".to_string();
+        send(
+            &games,
+            BOB,
+            UpdateGameRequest::DemotePlayer { demote_username },
+        )
+        .unwrap();
+
+        send(&games, BOB, UpdateGameRequest::UndoLastAction).unwrap();
+        assert_eq!(score(&games, "alice"), 0);
+        let game_entry = games.get(GAME_ID).unwrap();
+        let roles: Vec<_> = game_entry.game.players.iter().map(|x| &x.role).collect();
+        assert_eq!(
+            roles,
+            [
+                &PlayerRole::Spectator,
+                &PlayerRole::Contestant,
+                &PlayerRole::Host
+            ]
+        );
+    }
+
+    #[test]
+    fn undo_keeps_teams_but_restores_their_scores() {
+        let games = new_game(settings(), vec![question("q1", 100)]);
+        same_team(&games);
+        buzz_in(&games, "q1", ALICE);
+        send(
+            &games,
+            HOST,
+            UpdateGameRequest::ConfirmAnswer { is_correct: true },
+        )
+        .unwrap();
+
+        send(&games, HOST, UpdateGameRequest::UndoLastAction).unwrap();
+        let game_entry = games.get(GAME_ID).unwrap();
+        assert_eq!(game_entry.game.teams[0].score, 0);
+        assert!(game_entry
+            .game
+            .players
+            .iter()
+            .filter(|x| x.role == PlayerRole::Contestant)
+            .all(|x| x.team_id.as_deref() == Some("team-1")));
+    }
+
+    #[test]
+    fn teams_make_one_final_round_wager() {
+        let games = new_game(settings(), vec![question("q1", 300)]);
+        {
+            let mut game_entry = games.get_mut(GAME_ID).unwrap();
+            game_entry.game.players.push(Player {
+                username: "carol".to_string(),
+                score: 0,
+                role: PlayerRole::Contestant,
+                team_id: None,
+            });
+            game_entry
+                .auth
+                .player_tokens
+                .insert("carol-token".to_string(), "carol".to_string());
+            game_entry.game.final_round = Some(FinalRound {
+                name: "Final".to_string(),
+                question: question("final", 0),
+                wagers: HashMap::new(),
+                answers: HashMap::new(),
+                revealed: Vec::new(),
+            });
+            award_points(&mut game_entry.game, "carol".to_string(), 100).unwrap();
+        }
+        same_team(&games);
+        buzz_in(&games, "q1", ALICE);
+        send(
+            &games,
+            HOST,
+            UpdateGameRequest::ConfirmAnswer { is_correct: true },
+        )
+        .unwrap();
+
+        // The team has 300 between them, and bob's wager replaces alice's
+        send(
+            &games,
+            ALICE,
+            UpdateGameRequest::SubmitWager { amount: 300 },
+        )
+        .unwrap();
+        send(&games, BOB, UpdateGameRequest::SubmitWager { amount: 200 }).unwrap();
+        send(
+            &games,
+            "carol-token",
+            UpdateGameRequest::SubmitWager { amount: 100 },
+        )
+        .unwrap();
+        send(&games, HOST, UpdateGameRequest::ShowFinalQuestion).unwrap();
+        for (token, answer) in [(ALICE, "The Answer"), ("carol-token", "The Answer")] {
+            let answer = answer.to_string();
+            send(
+                &games,
+                token,
+                UpdateGameRequest::SubmitFinalAnswer { answer },
+            )
+            .unwrap();
+        }
+
+        // Carol has 100 to the team's 300, so goes first
+        send(&games, HOST, UpdateGameRequest::RevealFinalAnswer).unwrap();
+        assert!(matches!(
+            state(&games),
+            GameState::FinalRoundReveal { username, .. } if username == "carol"
+        ));
+        let score_answer = |is_correct| UpdateGameRequest::ScoreFinalAnswer { is_correct };
+        send(&games, HOST, score_answer(true)).unwrap();
+        assert!(matches!(
+            state(&games),
+            GameState::FinalRoundReveal { username, wager: 200, .. } if username == "bob"
+        ));
+        send(&games, HOST, score_answer(true)).unwrap();
+
+        assert_eq!(state(&games), GameState::Finished);
+        let game_entry = games.get(GAME_ID).unwrap();
+        assert_eq!(game_entry.game.teams[0].score, 500);
+        assert_eq!(get_score(&game_entry.game, "carol"), Some(200));
+    }
+
+    #[test]
+    fn games_with_longer_ids_can_be_joined() {
+        // Such as a game restored from disk after the configured ID length was reduced
+        let games = new_game(settings(), vec![question("q1", 100)]);
+        let (_, game_entry) = games.remove(GAME_ID).unwrap();
+        games.insert("RESTORED".to_string(), game_entry);
+        let state = app_state(games);
+
+        let username = "carol".to_string();
+        let result = state.clone().join_game(
+            "restored".to_string(),
+            username,
+            PlayerRole::Spectator,
+            None,
+            None,
+        );
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn host_token_falls_back_to_another_host() {
+        let games = new_game(settings(), vec![question("q1", 100)]);
+        games.get_mut(GAME_ID).unwrap().auth.host_username = Some("host".to_string());
+        let promote_username = "alice".to_string();
+        send(
+            &games,
+            HOST,
+            UpdateGameRequest::PromotePlayer { promote_username },
+        )
+        .unwrap();
+        let demote_username = "host".to_string();
+        send(
+            &games,
+            ALICE,
+            UpdateGameRequest::DemotePlayer { demote_username },
+        )
+        .unwrap();
+
+        let game_entry = games.get(GAME_ID).unwrap();
+        assert_eq!(game_entry.host_player_token(), Some(&ALICE.to_string()));
+    }
+}
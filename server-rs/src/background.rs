@@ -1,28 +1,82 @@
 use std::time::Duration;
 
-use tokio::time::Instant;
+use tokio::{sync::watch, time::Instant};
 
-use crate::models::AppState;
+use crate::{
+    models::{AppState, GameState, GameTimer},
+    util::get_time,
+};
 
 pub fn start_cleanup_old_games(state: AppState) {
     tokio::spawn(async move {
         // Cleanup old games on a schedule
-        let mut interval = tokio::time::interval(Duration::from_secs(60));
+        let config = state.config.clone();
+        let mut interval = tokio::time::interval(Duration::from_secs(config.cleanup_interval_secs));
         loop {
             interval.tick().await;
             tracing::debug!("Searching for stale games to remove");
             let now = Instant::now();
-            // Find games that haven't been updated in more than 30 mins
+            // Find games that haven't been updated for a while, allowing longer for games that
+            // have been paused for a break
             let stale_games: Vec<String> = state
                 .games
                 .iter()
-                .filter(|x| now.duration_since(x.last_updated) > Duration::from_secs(1800))
+                .filter(|x| {
+                    let timeout = match x.game.state {
+                        GameState::Paused { .. } => config.stale_paused_game_secs,
+                        _ => config.stale_game_secs,
+                    };
+                    now.duration_since(x.last_updated) > Duration::from_secs(timeout)
+                })
                 .map(|x| x.key().clone())
                 .collect();
 
             for game_id in stale_games {
                 tracing::info!("Removing game {game_id} as it is stale");
                 state.games.remove(&game_id);
+                state.metrics.games_cleaned_up.inc();
+                state.remove_saved_game(&game_id);
+            }
+        }
+    });
+}
+
+/// Run the timers for a single game, such as closing the buzz window. The task ends once the game
+/// is removed, as that drops the sending half of the channel.
+pub fn start_game_timers(
+    state: AppState,
+    game_id: String,
+    mut timer: watch::Receiver<Option<GameTimer>>,
+) {
+    tokio::spawn(async move {
+        loop {
+            let current = timer.borrow_and_update().clone();
+            let current = match current {
+                Some(x) => x,
+                None => {
+                    if timer.changed().await.is_err() {
+                        return;
+                    }
+                    continue;
+                }
+            };
+
+            let remaining = Duration::from_millis(current.deadline.saturating_sub(get_time()));
+            tokio::select! {
+                _ = tokio::time::sleep(remaining) => {
+                    let mut game_entry = match state.games.get_mut(&game_id) {
+                        Some(x) => x,
+                        None => return,
+                    };
+                    if let Err(e) = state.process_timer(&mut game_entry, current) {
+                        tracing::debug!("Timer for game {game_id} did not apply: {e:?}");
+                    }
+                }
+                changed = timer.changed() => {
+                    if changed.is_err() {
+                        return;
+                    }
+                }
             }
         }
     });
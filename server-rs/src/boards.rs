@@ -0,0 +1,529 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use axum::{
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    actions::{self, GameError},
+    dto::{BoardSummary, SaveBoardRequest},
+    models::{AppState, Category, FinalRound, GameSettings},
+    store::{FileWriter, StoreError},
+    util::{bearer_token, generate_token, get_time},
+};
+
+/// A saved game template in the question bank, which any number of games can be created from.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Board {
+    pub board_id: String,
+    pub name: String,
+    pub rounds: Vec<Vec<Category>>,
+    #[serde(default)]
+    pub final_round: Option<FinalRound>,
+    #[serde(default)]
+    pub settings: GameSettings,
+    pub created: u64,
+    pub updated: u64,
+    /// How often each question has been played, by question ID
+    #[serde(default)]
+    pub usage: HashMap<String, QuestionUsage>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct QuestionUsage {
+    pub times_played: u32,
+    pub last_played: u64,
+}
+
+impl IntoResponse for Board {
+    fn into_response(self) -> axum::response::Response {
+        return (StatusCode::OK, Json(self)).into_response();
+    }
+}
+
+impl Board {
+    fn question_ids(&self) -> impl Iterator<Item = &String> {
+        return self
+            .rounds
+            .iter()
+            .flatten()
+            .flat_map(|x| x.questions.iter())
+            .chain(self.final_round.iter().map(|x| &x.question))
+            .map(|x| &x.question_id);
+    }
+
+    fn tags(&self) -> BTreeSet<&String> {
+        return self
+            .rounds
+            .iter()
+            .flatten()
+            .flat_map(|x| x.questions.iter())
+            .chain(self.final_round.iter().map(|x| &x.question))
+            .flat_map(|x| x.tags.iter())
+            .collect();
+    }
+}
+
+impl Into<BoardSummary> for &Board {
+    fn into(self) -> BoardSummary {
+        BoardSummary {
+            board_id: self.board_id.clone(),
+            name: self.name.clone(),
+            tags: self.tags().into_iter().cloned().collect(),
+            question_count: self.question_ids().count(),
+            times_played: self.usage.values().map(|x| x.times_played).sum(),
+            last_played: self.usage.values().map(|x| x.last_played).max(),
+            created: self.created,
+            updated: self.updated,
+        }
+    }
+}
+
+/// Storage backend used to persist boards so they survive a server restart.
+pub trait BoardStore: Send + Sync {
+    fn save(&self, board: &Board) -> Result<(), StoreError>;
+    fn remove(&self, board_id: &str) -> Result<(), StoreError>;
+    fn load_all(&self) -> Result<Vec<Board>, StoreError>;
+    /// Wait for every save and removal so far to be written.
+    fn flush(&self);
+}
+
+/// Keeps boards only in `AppState.boards`, so nothing survives a restart.
+pub struct MemoryBoardStore;
+
+impl BoardStore for MemoryBoardStore {
+    fn save(&self, _board: &Board) -> Result<(), StoreError> {
+        return Ok(());
+    }
+
+    fn remove(&self, _board_id: &str) -> Result<(), StoreError> {
+        return Ok(());
+    }
+
+    fn load_all(&self) -> Result<Vec<Board>, StoreError> {
+        return Ok(Vec::new());
+    }
+
+    fn flush(&self) {}
+}
+
+/// Stores each board as a JSON file named after the board ID inside a directory.
+pub struct FileBoardStore {
+    dir: PathBuf,
+    writer: FileWriter,
+}
+
+impl FileBoardStore {
+    pub fn new(dir: impl AsRef<Path>) -> Result<FileBoardStore, StoreError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        return Ok(FileBoardStore {
+            dir,
+            writer: FileWriter::new(),
+        });
+    }
+
+    fn path_for(&self, board_id: &str) -> PathBuf {
+        return self.dir.join(format!("{board_id}.json"));
+    }
+}
+
+impl BoardStore for FileBoardStore {
+    fn save(&self, board: &Board) -> Result<(), StoreError> {
+        self.writer
+            .write(self.path_for(&board.board_id), serde_json::to_vec(board)?);
+        return Ok(());
+    }
+
+    fn remove(&self, board_id: &str) -> Result<(), StoreError> {
+        self.writer.remove(self.path_for(board_id));
+        return Ok(());
+    }
+
+    fn load_all(&self) -> Result<Vec<Board>, StoreError> {
+        let mut boards = Vec::new();
+        for file in fs::read_dir(&self.dir)? {
+            let path = file?.path();
+            if path.extension().is_none_or(|x| x != "json") {
+                continue;
+            }
+
+            let parsed = fs::read(&path)
+                .map_err(StoreError::from)
+                .and_then(|x| serde_json::from_slice::<Board>(&x).map_err(StoreError::from));
+            match parsed {
+                Ok(board) => boards.push(board),
+                Err(e) => tracing::warn!("Skipping unreadable board file {path:?}: {e}"),
+            }
+        }
+        return Ok(boards);
+    }
+
+    fn flush(&self) {
+        self.writer.flush();
+    }
+}
+
+impl AppState {
+    /// Check the request is allowed to use the question bank, which needs the configured
+    /// `boards_token` when there is one.
+    pub fn authorize_boards(&self, headers: &HeaderMap) -> Result<(), GameError> {
+        return match &self.config.boards_token {
+            Some(token) if bearer_token(headers) != Some(token.as_str()) => {
+                Err(GameError::InvalidToken)
+            }
+            _ => Ok(()),
+        };
+    }
+
+    pub fn list_boards(&self, tag: Option<String>) -> Vec<BoardSummary> {
+        let mut boards: Vec<BoardSummary> = self
+            .boards
+            .iter()
+            .map(|x| x.value().into())
+            .filter(|x: &BoardSummary| tag.as_ref().is_none_or(|tag| x.tags.contains(tag)))
+            .collect();
+        boards.sort_by(|a, b| a.name.cmp(&b.name));
+        return boards;
+    }
+
+    pub fn get_board(&self, board_id: &str) -> Result<Board, GameError> {
+        return match self.boards.get(board_id) {
+            Some(x) => Ok(x.clone()),
+            None => Err(GameError::BoardNotFound),
+        };
+    }
+
+    pub fn create_board(&self, request: SaveBoardRequest) -> Result<Board, GameError> {
+        let mut board = Board {
+            board_id: generate_token(),
+            name: String::new(),
+            rounds: Vec::new(),
+            final_round: None,
+            settings: GameSettings::default(),
+            created: get_time(),
+            updated: get_time(),
+            usage: HashMap::new(),
+        };
+        apply_board_request(&mut board, request)?;
+
+        self.save_board(&board);
+        self.boards.insert(board.board_id.clone(), board.clone());
+        return Ok(board);
+    }
+
+    /// Replace the contents of a board, keeping the usage of any questions still on it.
+    pub fn update_board(
+        &self,
+        board_id: &str,
+        request: SaveBoardRequest,
+    ) -> Result<Board, GameError> {
+        let mut board = match self.boards.get_mut(board_id) {
+            Some(x) => x,
+            None => return Err(GameError::BoardNotFound),
+        };
+
+        let mut updated = board.clone();
+        apply_board_request(&mut updated, request)?;
+        let question_ids: Vec<String> = updated.question_ids().cloned().collect();
+        updated.usage.retain(|id, _| question_ids.contains(id));
+        updated.updated = get_time();
+
+        self.save_board(&updated);
+        *board = updated.clone();
+        return Ok(updated);
+    }
+
+    pub fn delete_board(&self, board_id: &str) -> Result<(), GameError> {
+        if self.boards.remove(board_id).is_none() {
+            return Err(GameError::BoardNotFound);
+        }
+        if let Err(e) = self.board_store.remove(board_id) {
+            tracing::error!("Failed to remove persisted board {board_id}: {e}");
+        }
+        return Ok(());
+    }
+
+    /// Count questions as played, or no longer played once they have been undone, for games
+    /// created from a board.
+    pub fn record_questions_played(
+        &self,
+        board_id: &str,
+        played: &[&String],
+        unplayed: &[&String],
+    ) {
+        let mut board = match self.boards.get_mut(board_id) {
+            Some(x) => x,
+            // The board may have been deleted since the game was created
+            None => return,
+        };
+
+        for question_id in played {
+            let usage = board.usage.entry(question_id.to_string()).or_default();
+            usage.times_played += 1;
+            usage.last_played = get_time();
+        }
+        for question_id in unplayed {
+            if let Some(usage) = board.usage.get_mut(*question_id) {
+                usage.times_played = usage.times_played.saturating_sub(1);
+            }
+        }
+        board.usage.retain(|_, x| x.times_played > 0);
+        self.save_board(&board);
+    }
+
+    /// Load all persisted boards back into memory.
+    pub fn restore_boards(&self) {
+        let boards = match self.board_store.load_all() {
+            Ok(x) => x,
+            Err(e) => {
+                tracing::error!("Failed to load persisted boards: {e}");
+                return;
+            }
+        };
+
+        for board in boards {
+            self.boards.insert(board.board_id.clone(), board);
+        }
+    }
+
+    fn save_board(&self, board: &Board) {
+        if let Err(e) = self.board_store.save(board) {
+            tracing::error!("Failed to persist board {}: {e}", board.board_id);
+        }
+    }
+}
+
+fn apply_board_request(board: &mut Board, mut request: SaveBoardRequest) -> Result<(), GameError> {
+    if request.name.trim().is_empty() {
+        return Err(GameError::InvalidBoardName);
+    }
+    actions::prepare_questions(&mut request.rounds, &mut request.final_round)?;
+    if !request.settings.has_valid_limits() {
+        return Err(GameError::InvalidTimeLimit);
+    }
+
+    board.name = request.name;
+    board.rounds = request.rounds;
+    board.final_round = request.final_round;
+    board.settings = request.settings;
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::http::{header, HeaderValue};
+
+    use super::*;
+    use crate::{
+        config::ServerConfig,
+        dto::{CreateGameRequest, UpdateGameRequest},
+        models::{PlayerRole, Question, QuestionKind},
+        store::MemoryGameStore,
+    };
+
+    fn new_state(boards_token: Option<&str>) -> AppState {
+        let config = ServerConfig {
+            boards_token: boards_token.map(|x| x.to_string()),
+            ..ServerConfig::default()
+        };
+        return AppState::new(
+            config,
+            Arc::new(MemoryGameStore),
+            Arc::new(MemoryBoardStore),
+        );
+    }
+
+    fn question(question_id: &str, tags: &[&str]) -> Question {
+        return Question {
+            question_id: question_id.to_string(),
+            detail: format!("Question {question_id}"),
+            correct_answer: "The Answer".to_string(),
+            value: 100,
+            answered: false,
+            daily_double: false,
+            alternate_answers: Vec::new(),
+            kind: QuestionKind::FreeResponse,
+            choices: Vec::new(),
+            tags: tags.iter().map(|x| x.to_string()).collect(),
+        };
+    }
+
+    fn board_request(name: &str, questions: Vec<Question>) -> SaveBoardRequest {
+        return SaveBoardRequest {
+            name: name.to_string(),
+            rounds: vec![vec![Category {
+                category_id: "c1".to_string(),
+                name: "Category".to_string(),
+                questions,
+            }]],
+            final_round: None,
+            settings: GameSettings::default(),
+        };
+    }
+
+    fn bearer(token: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let value = HeaderValue::from_str(&format!("Bearer {token}")).unwrap();
+        headers.insert(header::AUTHORIZATION, value);
+        return headers;
+    }
+
+    fn times_played(state: &AppState, board_id: &str, question_id: &str) -> Option<u32> {
+        let board = state.get_board(board_id).unwrap();
+        return board.usage.get(question_id).map(|x| x.times_played);
+    }
+
+    #[test]
+    fn boards_are_open_without_a_token_configured() {
+        let state = new_state(None);
+        assert!(state.authorize_boards(&HeaderMap::new()).is_ok());
+        assert!(state.authorize_boards(&bearer("anything")).is_ok());
+    }
+
+    #[test]
+    fn boards_need_the_configured_token() {
+        let state = new_state(Some("secret"));
+        assert!(state.authorize_boards(&bearer("secret")).is_ok());
+
+        let mut without_bearer = HeaderMap::new();
+        without_bearer.insert(header::AUTHORIZATION, HeaderValue::from_static("secret"));
+        for headers in [HeaderMap::new(), bearer("wrong"), without_bearer] {
+            let result = state.authorize_boards(&headers);
+            assert!(matches!(result, Err(GameError::InvalidToken)));
+        }
+    }
+
+    #[test]
+    fn list_boards_filters_by_tag() {
+        let state = new_state(None);
+        let science = vec![question("q1", &["science", "hard"]), question("q2", &[])];
+        state
+            .create_board(board_request("Science", science))
+            .unwrap();
+        let history = vec![question("q1", &["history"])];
+        state
+            .create_board(board_request("History", history))
+            .unwrap();
+
+        let names = |tag: Option<&str>| -> Vec<String> {
+            return state
+                .list_boards(tag.map(|x| x.to_string()))
+                .into_iter()
+                .map(|x| x.name)
+                .collect();
+        };
+        assert_eq!(names(None), ["History", "Science"]);
+        assert_eq!(names(Some("hard")), ["Science"]);
+        assert!(names(Some("sport")).is_empty());
+    }
+
+    #[test]
+    fn updating_a_board_keeps_usage_of_questions_still_on_it() {
+        let state = new_state(None);
+        let request = board_request("Board", vec![question("q1", &[]), question("q2", &[])]);
+        let board_id = state.create_board(request).unwrap().board_id;
+        let (q1, q2) = ("q1".to_string(), "q2".to_string());
+        state.record_questions_played(&board_id, &[&q1, &q2], &[]);
+
+        let request = board_request("Board", vec![question("q1", &[]), question("q3", &[])]);
+        state.update_board(&board_id, request).unwrap();
+
+        assert_eq!(times_played(&state, &board_id, "q1"), Some(1));
+        assert_eq!(times_played(&state, &board_id, "q2"), None);
+        assert_eq!(times_played(&state, &board_id, "q3"), None);
+    }
+
+    #[tokio::test]
+    async fn games_created_from_a_board_use_its_questions() {
+        let state = new_state(None);
+        let mut request = board_request("Board", vec![question("board-question", &[])]);
+        request.settings.buzz_window_ms = 0;
+        let board_id = state.create_board(request).unwrap().board_id;
+
+        let inline = board_request("Inline", vec![question("inline-question", &[])]);
+        let response = state
+            .clone()
+            .create_game(CreateGameRequest {
+                rounds: inline.rounds,
+                final_round: None,
+                settings: None,
+                board_id: Some(board_id.clone()),
+            })
+            .unwrap();
+
+        let questions: Vec<&String> = response
+            .game
+            .rounds
+            .iter()
+            .flatten()
+            .flat_map(|x| x.questions.iter())
+            .map(|x| &x.question_id)
+            .collect();
+        assert_eq!(questions, ["board-question"]);
+        assert_eq!(response.game.settings.buzz_window_ms, 0);
+        let game_entry = state.games.get(&response.game.id).unwrap();
+        assert_eq!(game_entry.auth.board_id, Some(board_id));
+    }
+
+    #[tokio::test]
+    async fn usage_is_counted_once_a_question_is_answered() {
+        let state = new_state(None);
+        let mut request = board_request("Board", vec![question("q1", &[]), question("q2", &[])]);
+        request.settings.buzz_window_ms = 0;
+        let board_id = state.create_board(request).unwrap().board_id;
+        let response = state
+            .clone()
+            .create_game(CreateGameRequest {
+                rounds: Vec::new(),
+                final_round: None,
+                settings: None,
+                board_id: Some(board_id.clone()),
+            })
+            .unwrap();
+
+        let game_id = response.game.id;
+        let join = |username: &str, role: PlayerRole, token: Option<String>| {
+            let (game_id, username) = (game_id.clone(), username.to_string());
+            return state
+                .clone()
+                .join_game(game_id, username, role, token, None)
+                .unwrap();
+        };
+        let host = join("host", PlayerRole::Host, Some(response.host_token));
+        let alice = join("alice", PlayerRole::Contestant, None);
+        let send = |token: &String, request: UpdateGameRequest| {
+            let mut game_entry = state.games.get_mut(&game_id).unwrap();
+            return state.process_game_request(&mut game_entry, token.clone(), request);
+        };
+
+        send(&host, UpdateGameRequest::StartGame).unwrap();
+        let question_id = "q1".to_string();
+        send(&host, UpdateGameRequest::PickQuestion { question_id }).unwrap();
+        assert_eq!(times_played(&state, &board_id, "q1"), None);
+
+        send(&host, UpdateGameRequest::AllowAnswering).unwrap();
+        send(&alice, UpdateGameRequest::AnswerQuestion).unwrap();
+        let confirm = UpdateGameRequest::ConfirmAnswer { is_correct: true };
+        send(&host, confirm.clone()).unwrap();
+        assert_eq!(times_played(&state, &board_id, "q1"), Some(1));
+
+        // Undoing and answering again still only counts the question once
+        send(&host, UpdateGameRequest::UndoLastAction).unwrap();
+        assert_eq!(times_played(&state, &board_id, "q1"), None);
+        send(&host, confirm).unwrap();
+        assert_eq!(times_played(&state, &board_id, "q1"), Some(1));
+        assert_eq!(times_played(&state, &board_id, "q2"), None);
+    }
+}
@@ -0,0 +1,285 @@
+use std::{fmt, fs, io, path::PathBuf};
+
+use clap::Parser;
+use serde::Deserialize;
+
+/// Settings for running the server. Each setting is taken from the command line if given, then
+/// environment variables, then the config file, and otherwise uses its default.
+#[derive(Clone, Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Address and port the server listens on
+    pub bind_address: String,
+    /// Directory the UI is served from
+    pub static_dir: PathBuf,
+    /// Directory games are persisted to. Games are only kept in memory when this isn't set.
+    pub data_dir: Option<PathBuf>,
+    /// How often to look for stale games to remove
+    pub cleanup_interval_secs: u64,
+    /// Time without any activity before a game is removed
+    pub stale_game_secs: u64,
+    /// Time without any activity before a paused game is removed
+    pub stale_paused_game_secs: u64,
+    pub max_username_length: usize,
+    pub game_id_length: usize,
+    /// Characters game IDs are made from, which must be uppercase letters or digits
+    pub game_id_alphabet: String,
+    /// Words game IDs must not contain, on top of the built in list
+    pub game_id_blocklist: Vec<String>,
+    /// Number of messages each game's channel holds before slow clients start to miss them
+    pub channel_capacity: usize,
+    /// Token needed to use the question bank, which is open to anyone when this isn't set
+    pub boards_token: Option<String>,
+    /// How long to wait for connections to close when shutting down
+    pub shutdown_timeout_secs: u64,
+    /// Sent to clients when shutting down to suggest when they should reconnect, for when the
+    /// server is being restarted
+    pub reconnect_after_secs: Option<u64>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind_address: "0.0.0.0:3000".to_string(),
+            static_dir: PathBuf::from("wwwroot"),
+            data_dir: None,
+            cleanup_interval_secs: 60,
+            stale_game_secs: 1800,
+            stale_paused_game_secs: 4 * 3600,
+            max_username_length: 20,
+            game_id_length: 4,
+            // Leave out I and O, which are easily mistaken for 1 and 0
+            game_id_alphabet: "ABCDEFGHJKLMNPQRSTUVWXYZ".to_string(),
+            game_id_blocklist: Vec::new(),
+            channel_capacity: 64,
+            boards_token: None,
+            shutdown_timeout_secs: 10,
+            reconnect_after_secs: None,
+        }
+    }
+}
+
+#[derive(Parser, Debug)]
+#[command(about = "Server for Open Trivia Night")]
+struct Args {
+    /// Path to a TOML config file
+    #[arg(long, env = "CONFIG_FILE")]
+    config: Option<PathBuf>,
+    /// Address and port to listen on [default: 0.0.0.0:3000]
+    #[arg(long, env = "BIND_ADDRESS")]
+    bind_address: Option<String>,
+    /// Directory the UI is served from [default: wwwroot]
+    #[arg(long, env = "STATIC_DIR")]
+    static_dir: Option<PathBuf>,
+    /// Directory to persist games to, otherwise games are only kept in memory
+    #[arg(long, env = "DATA_DIR")]
+    data_dir: Option<PathBuf>,
+    /// Seconds between checks for stale games [default: 60]
+    #[arg(long, env = "CLEANUP_INTERVAL_SECS")]
+    cleanup_interval_secs: Option<u64>,
+    /// Seconds without activity before a game is removed [default: 1800]
+    #[arg(long, env = "STALE_GAME_SECS")]
+    stale_game_secs: Option<u64>,
+    /// Seconds without activity before a paused game is removed [default: 14400]
+    #[arg(long, env = "STALE_PAUSED_GAME_SECS")]
+    stale_paused_game_secs: Option<u64>,
+    /// Longest username players may join with [default: 20]
+    #[arg(long, env = "MAX_USERNAME_LENGTH")]
+    max_username_length: Option<usize>,
+    /// Number of letters in new game IDs [default: 4]
+    #[arg(long, env = "GAME_ID_LENGTH")]
+    game_id_length: Option<usize>,
+    /// Characters game IDs are made from [default: A-Z without I and O]
+    #[arg(long, env = "GAME_ID_ALPHABET")]
+    game_id_alphabet: Option<String>,
+    /// Comma separated words game IDs must not contain, on top of the built in list
+    #[arg(long, env = "GAME_ID_BLOCKLIST", value_delimiter = ',')]
+    game_id_blocklist: Option<Vec<String>>,
+    /// Messages buffered per game before slow clients are resynced [default: 64]
+    #[arg(long, env = "CHANNEL_CAPACITY")]
+    channel_capacity: Option<usize>,
+    /// Token needed to use the question bank, which is open to anyone otherwise
+    #[arg(long, env = "BOARDS_TOKEN")]
+    boards_token: Option<String>,
+    /// Seconds to wait for connections to close when shutting down [default: 10]
+    #[arg(long, env = "SHUTDOWN_TIMEOUT_SECS")]
+    shutdown_timeout_secs: Option<u64>,
+    /// Seconds clients are told to wait before reconnecting when the server shuts down
+    #[arg(long, env = "RECONNECT_AFTER_SECS")]
+    reconnect_after_secs: Option<u64>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Parse(toml::de::Error),
+    Invalid(&'static str),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "could not read config file {path:?}: {e}"),
+            ConfigError::Parse(e) => write!(f, "invalid config file: {e}"),
+            ConfigError::Invalid(e) => write!(f, "invalid config: {e}"),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Load the config from the command line, environment variables and config file.
+    pub fn load() -> Result<ServerConfig, ConfigError> {
+        return ServerConfig::from_args(Args::parse());
+    }
+
+    fn from_args(args: Args) -> Result<ServerConfig, ConfigError> {
+        let mut config = match &args.config {
+            Some(path) => {
+                let contents =
+                    fs::read_to_string(path).map_err(|e| ConfigError::Io(path.clone(), e))?;
+                toml::from_str(&contents).map_err(ConfigError::Parse)?
+            }
+            None => ServerConfig::default(),
+        };
+
+        if let Some(x) = args.bind_address {
+            config.bind_address = x;
+        }
+        if let Some(x) = args.static_dir {
+            config.static_dir = x;
+        }
+        if let Some(x) = args.data_dir {
+            config.data_dir = Some(x);
+        }
+        if let Some(x) = args.cleanup_interval_secs {
+            config.cleanup_interval_secs = x;
+        }
+        if let Some(x) = args.stale_game_secs {
+            config.stale_game_secs = x;
+        }
+        if let Some(x) = args.stale_paused_game_secs {
+            config.stale_paused_game_secs = x;
+        }
+        if let Some(x) = args.max_username_length {
+            config.max_username_length = x;
+        }
+        if let Some(x) = args.game_id_length {
+            config.game_id_length = x;
+        }
+        if let Some(x) = args.game_id_alphabet {
+            config.game_id_alphabet = x;
+        }
+        if let Some(x) = args.game_id_blocklist {
+            config.game_id_blocklist = x;
+        }
+        if let Some(x) = args.channel_capacity {
+            config.channel_capacity = x;
+        }
+        if let Some(x) = args.boards_token {
+            config.boards_token = Some(x);
+        }
+        if let Some(x) = args.shutdown_timeout_secs {
+            config.shutdown_timeout_secs = x;
+        }
+        if let Some(x) = args.reconnect_after_secs {
+            config.reconnect_after_secs = Some(x);
+        }
+
+        config.validate()?;
+        return Ok(config);
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.cleanup_interval_secs == 0 {
+            return Err(ConfigError::Invalid(
+                "cleanup_interval_secs must be at least 1",
+            ));
+        }
+        if self.max_username_length == 0 {
+            return Err(ConfigError::Invalid(
+                "max_username_length must be at least 1",
+            ));
+        }
+        if self.game_id_length == 0 {
+            return Err(ConfigError::Invalid("game_id_length must be at least 1"));
+        }
+        // Game IDs are uppercased when looked up, so lowercase letters would never match
+        if !self
+            .game_id_alphabet
+            .chars()
+            .all(|x| x.is_ascii_uppercase() || x.is_ascii_digit())
+        {
+            return Err(ConfigError::Invalid(
+                "game_id_alphabet must only contain uppercase letters and digits",
+            ));
+        }
+        let mut alphabet: Vec<char> = self.game_id_alphabet.chars().collect();
+        alphabet.sort();
+        alphabet.dedup();
+        if alphabet.len() < 2 {
+            return Err(ConfigError::Invalid(
+                "game_id_alphabet must contain at least 2 different characters",
+            ));
+        }
+        if self.channel_capacity == 0 {
+            return Err(ConfigError::Invalid("channel_capacity must be at least 1"));
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    fn load(args: &[&str], config_file: Option<&str>) -> Result<ServerConfig, ConfigError> {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        let mut all_args = vec!["server-rs".to_string()];
+        if let Some(contents) = config_file {
+            file.write_all(contents.as_bytes()).unwrap();
+            all_args.push("--config".to_string());
+            all_args.push(file.path().to_string_lossy().to_string());
+        }
+        all_args.extend(args.iter().map(|x| x.to_string()));
+        return ServerConfig::from_args(Args::try_parse_from(all_args).unwrap());
+    }
+
+    #[test]
+    fn flags_take_priority_over_the_config_file() {
+        let config_file = "bind_address = \"127.0.0.1:1\"\nstale_game_secs = 5\n";
+        let config = load(&["--bind-address", "127.0.0.1:2"], Some(config_file)).unwrap();
+
+        assert_eq!(config.bind_address, "127.0.0.1:2");
+        assert_eq!(config.stale_game_secs, 5);
+        assert_eq!(
+            config.cleanup_interval_secs,
+            ServerConfig::default().cleanup_interval_secs
+        );
+    }
+
+    #[test]
+    fn blocklist_flag_is_comma_separated() {
+        let config = load(&["--game-id-blocklist", "BAD,WORD"], None).unwrap();
+        assert_eq!(config.game_id_blocklist, ["BAD", "WORD"]);
+    }
+
+    #[test]
+    fn unknown_config_file_keys_are_rejected() {
+        let result = load(&[], Some("bind_adress = \"127.0.0.1:1\"\n"));
+        assert!(matches!(result, Err(ConfigError::Parse(_))));
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        for args in [
+            ["--game-id-alphabet", "abc"],
+            ["--game-id-alphabet", "AAAA"],
+            ["--channel-capacity", "0"],
+            ["--game-id-length", "0"],
+        ] {
+            assert!(matches!(load(&args, None), Err(ConfigError::Invalid(_))));
+        }
+    }
+}
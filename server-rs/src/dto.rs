@@ -3,13 +3,20 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     actions::GameError,
-    models::{Category, Game, GameLog, GameState, Player, Question},
+    models::{
+        Category, FinalRound, Game, GameLog, GameSettings, GameState, GameTimer, Player,
+        PlayerRole, Question, Team,
+    },
     util::get_time,
 };
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 #[serde(tag = "type")]
 pub enum GameMessage {
+    /// Sent only to the newly connected player, containing the token they must use to reconnect
+    Authenticated {
+        token: String,
+    },
     JoinGame {
         game: GameOverview,
     },
@@ -19,16 +26,100 @@ pub enum GameMessage {
     QuestionUpdate {
         question: Question,
     },
+    /// Sent to a client that fell behind and missed messages, with the full state of the game
+    Resync {
+        game: Box<Game>,
+    },
+    /// Sent only to the connection whose request failed, with the `requestId` it gave if any
+    #[serde(rename_all = "camelCase")]
     ReportError {
         error: GameError,
         message: &'static str,
-        username: String,
+        request_id: Option<String>,
+    },
+    /// Sent only to the connection whose request succeeded, when it gave a `requestId`
+    #[serde(rename_all = "camelCase")]
+    Ack {
+        request_id: String,
     },
     EndSession {
         username: String,
+        #[serde(default)]
+        reason: EndSessionReason,
+    },
+    /// Sent to everyone just before the server stops, after which their connection is closed
+    #[serde(rename_all = "camelCase")]
+    ServerShuttingDown {
+        /// How long clients should wait before trying to reconnect, if the server is restarting
+        reconnect_after_secs: Option<u64>,
     },
 }
 
+/// Close code for connections closed because the server is shutting down
+pub const SERVER_SHUTTING_DOWN_CLOSE_CODE: u16 = 3010;
+
+/// Why a player's session was ended, which decides the close code sent to their WebSocket.
+#[derive(Clone, Serialize, Deserialize, PartialEq, Debug, Default)]
+pub enum EndSessionReason {
+    #[default]
+    Left,
+    Kicked,
+    Banned,
+}
+
+impl EndSessionReason {
+    pub fn get_close_code(&self) -> u16 {
+        return match self {
+            EndSessionReason::Left => 3001,
+            EndSessionReason::Kicked => 3003,
+            EndSessionReason::Banned => 3004,
+        };
+    }
+
+    pub fn get_message(&self) -> &'static str {
+        return match self {
+            EndSessionReason::Left => "User requested to leave the game",
+            EndSessionReason::Kicked => "You were removed from the game by the Host",
+            EndSessionReason::Banned => "You were banned from the game by the Host",
+        };
+    }
+}
+
+/// A message sent to clients, numbered in the order it was sent so that a reconnecting client can
+/// ask for the messages it missed.
+#[derive(Clone, Serialize, Debug)]
+pub struct SequencedMessage {
+    pub seq: u64,
+    #[serde(flatten)]
+    pub message: GameMessage,
+}
+
+impl GameMessage {
+    /// Hosts see everything, while anyone else only sees answers once they have been revealed.
+    pub fn redacted_for(self, role: &PlayerRole) -> GameMessage {
+        if *role == PlayerRole::Host {
+            return self;
+        }
+        return match self {
+            GameMessage::JoinGame { mut game } => {
+                game.state = game.state.redacted();
+                GameMessage::JoinGame { game }
+            }
+            GameMessage::GameUpdate { mut game } => {
+                game.state = game.state.redacted();
+                GameMessage::GameUpdate { game }
+            }
+            GameMessage::QuestionUpdate { question } => GameMessage::QuestionUpdate {
+                question: question.redacted(),
+            },
+            GameMessage::Resync { game } => GameMessage::Resync {
+                game: Box::new(game.redacted()),
+            },
+            x => x,
+        };
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct GameOverview {
@@ -38,6 +129,9 @@ pub struct GameOverview {
     pub state: GameState,
     pub last_log_index: usize,
     pub last_log: Option<GameLog>,
+    /// The active countdown, so clients can show how long is left
+    pub timer: Option<GameTimer>,
+    pub teams: Vec<Team>,
 }
 
 impl Into<GameOverview> for &Game {
@@ -49,6 +143,8 @@ impl Into<GameOverview> for &Game {
             state: self.state.clone(),
             last_log_index: self.log.len().saturating_sub(1),
             last_log: self.log.last().cloned(),
+            timer: self.timer.clone(),
+            teams: self.teams.clone(),
         }
     }
 }
@@ -74,11 +170,135 @@ pub enum UpdateGameRequest {
         update_username: String,
         new_score: isize,
     },
+    SubmitWager {
+        amount: isize,
+    },
+    ShowFinalQuestion,
+    SubmitFinalAnswer {
+        answer: String,
+    },
+    RevealFinalAnswer,
+    #[serde(rename_all = "camelCase")]
+    ScoreFinalAnswer {
+        is_correct: bool,
+    },
+    CreateTeam {
+        name: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    RenameTeam {
+        team_id: String,
+        name: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    RemoveTeam {
+        team_id: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    AssignTeam {
+        update_username: String,
+        team_id: Option<String>,
+    },
+    BalanceTeams,
+    SubmitAnswer {
+        answer: String,
+    },
+    SubmitChoice {
+        index: usize,
+    },
+    UndoLastAction,
+    #[serde(rename_all = "camelCase")]
+    KickPlayer {
+        kick_username: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    BanPlayer {
+        ban_username: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    PromotePlayer {
+        promote_username: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    DemotePlayer {
+        demote_username: String,
+    },
+    PauseGame,
+    ResumeGame,
+}
+
+impl UpdateGameRequest {
+    /// Name of the request, without any of its details, to label metrics with.
+    pub fn kind(&self) -> &'static str {
+        return match self {
+            UpdateGameRequest::StartGame => "StartGame",
+            UpdateGameRequest::LeaveGame => "LeaveGame",
+            UpdateGameRequest::PickQuestion { .. } => "PickQuestion",
+            UpdateGameRequest::AllowAnswering => "AllowAnswering",
+            UpdateGameRequest::AnswerQuestion => "AnswerQuestion",
+            UpdateGameRequest::ConfirmAnswer { .. } => "ConfirmAnswer",
+            UpdateGameRequest::EndQuestion => "EndQuestion",
+            UpdateGameRequest::UpdatePlayerScore { .. } => "UpdatePlayerScore",
+            UpdateGameRequest::SubmitWager { .. } => "SubmitWager",
+            UpdateGameRequest::ShowFinalQuestion => "ShowFinalQuestion",
+            UpdateGameRequest::SubmitFinalAnswer { .. } => "SubmitFinalAnswer",
+            UpdateGameRequest::RevealFinalAnswer => "RevealFinalAnswer",
+            UpdateGameRequest::ScoreFinalAnswer { .. } => "ScoreFinalAnswer",
+            UpdateGameRequest::CreateTeam { .. } => "CreateTeam",
+            UpdateGameRequest::RenameTeam { .. } => "RenameTeam",
+            UpdateGameRequest::RemoveTeam { .. } => "RemoveTeam",
+            UpdateGameRequest::AssignTeam { .. } => "AssignTeam",
+            UpdateGameRequest::BalanceTeams => "BalanceTeams",
+            UpdateGameRequest::SubmitAnswer { .. } => "SubmitAnswer",
+            UpdateGameRequest::SubmitChoice { .. } => "SubmitChoice",
+            UpdateGameRequest::UndoLastAction => "UndoLastAction",
+            UpdateGameRequest::KickPlayer { .. } => "KickPlayer",
+            UpdateGameRequest::BanPlayer { .. } => "BanPlayer",
+            UpdateGameRequest::PromotePlayer { .. } => "PromotePlayer",
+            UpdateGameRequest::DemotePlayer { .. } => "DemotePlayer",
+            UpdateGameRequest::PauseGame => "PauseGame",
+            UpdateGameRequest::ResumeGame => "ResumeGame",
+        };
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GetGameQuery {
+    /// Only a Host's token gives access to the answers
+    pub token: Option<String>,
+}
+
+/// A request sent over the WebSocket. The client can include a `requestId` to match up the reply.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientRequest {
+    pub request_id: Option<String>,
+    #[serde(flatten)]
+    pub request: UpdateGameRequest,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
+pub struct JoinGameQuery {
+    pub token: Option<String>,
+    /// Team for a new contestant to join, when playing in teams
+    pub team: Option<String>,
+    /// Sequence number of the last message the client received, to only be sent what was missed
+    pub since: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
 pub struct CreateGameRequest {
+    #[serde(default)]
     pub rounds: Vec<Vec<Category>>,
+    #[serde(default)]
+    pub final_round: Option<FinalRound>,
+    /// Defaults to the board's settings when creating the game from a board
+    #[serde(default)]
+    pub settings: Option<GameSettings>,
+    /// Saved board to take the rounds and final round from, instead of giving them inline
+    #[serde(default)]
+    pub board_id: Option<String>,
 }
 
 impl Into<Game> for CreateGameRequest {
@@ -91,10 +311,35 @@ impl Into<Game> for CreateGameRequest {
             rounds: self.rounds,
             state: GameState::WaitingToStart,
             log: vec![GameLog::GameCreated { time: get_time() }],
+            final_round: self.final_round,
+            settings: self.settings.unwrap_or_default(),
+            timer: None,
+            teams: Vec::new(),
         }
     }
 }
 
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateGameResponse {
+    #[serde(flatten)]
+    pub game: Game,
+    /// Secret the host must present when joining, so nobody else can claim the Host role
+    pub host_token: String,
+}
+
+impl IntoResponse for GameOverview {
+    fn into_response(self) -> axum::response::Response {
+        return (StatusCode::OK, Json(self)).into_response();
+    }
+}
+
+impl IntoResponse for CreateGameResponse {
+    fn into_response(self) -> axum::response::Response {
+        return (StatusCode::OK, Json(self)).into_response();
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub struct GameErrorResponse {
     error: GameError,
@@ -102,10 +347,44 @@ pub struct GameErrorResponse {
 }
 
 impl GameError {
+    /// Name of the error, to label metrics with.
+    pub fn kind(&self) -> &'static str {
+        return match self {
+            GameError::InsufficientPermissions => "InsufficientPermissions",
+            GameError::InvalidGameState => "InvalidGameState",
+            GameError::FailedToCreateGame => "FailedToCreateGame",
+            GameError::GameNotFound => "GameNotFound",
+            GameError::UsernameTooLong => "UsernameTooLong",
+            GameError::QuestionNotFound => "QuestionNotFound",
+            GameError::PlayerNotFound => "PlayerNotFound",
+            GameError::MissingQuestions => "MissingQuestions",
+            GameError::NewPlayerCannotJoinAfterStart => "NewPlayerCannotJoinAfterStart",
+            GameError::AlreadyAnswered => "AlreadyAnswered",
+            GameError::InvalidToken => "InvalidToken",
+            GameError::UsernameTaken => "UsernameTaken",
+            GameError::InvalidWager => "InvalidWager",
+            GameError::MissingWagers => "MissingWagers",
+            GameError::TeamNotFound => "TeamNotFound",
+            GameError::InvalidQuestionChoices => "InvalidQuestionChoices",
+            GameError::ChoiceNotFound => "ChoiceNotFound",
+            GameError::NothingToUndo => "NothingToUndo",
+            GameError::PlayerBanned => "PlayerBanned",
+            GameError::LastHost => "LastHost",
+            GameError::GamePaused => "GamePaused",
+            GameError::ServerShuttingDown => "ServerShuttingDown",
+            GameError::BoardNotFound => "BoardNotFound",
+            GameError::InvalidBoardName => "InvalidBoardName",
+            GameError::InvalidCsv => "InvalidCsv",
+            GameError::InvalidRoundNumber => "InvalidRoundNumber",
+            GameError::InvalidQuestionValue => "InvalidQuestionValue",
+            GameError::InvalidTimeLimit => "InvalidTimeLimit",
+        };
+    }
+
     pub fn get_message(&self) -> &'static str {
         return match self {
             GameError::GameNotFound => "Game could not be found.",
-            GameError::UsernameTooLong => "Usernames must be less than 20 characters long.",
+            GameError::UsernameTooLong => "Username is longer than this server allows.",
             GameError::InsufficientPermissions => {
                 "User has insufficient permissions to perform this action."
             }
@@ -116,10 +395,28 @@ impl GameError {
                 "Player performing the action could not be found in the Game."
             }
             GameError::MissingQuestions => {
-                "Game must contain at least 1 round, where all rounds contain at least 1 category, with at least 1 question"
+                "Game must contain at least 1 round, where all rounds contain at least 1 category, with at least 1 question. Every question needs a question and an answer."
             },
             GameError::NewPlayerCannotJoinAfterStart => "New Contestants cannot join a game after it has started.",
-            GameError::AlreadyAnswered => "Another player buzzed in first."
+            GameError::AlreadyAnswered => "Another player buzzed in first.",
+            GameError::InvalidToken => "The token provided is not valid for this game.",
+            GameError::UsernameTaken => "Another player is already using this username. Use your reconnect token to rejoin.",
+            GameError::InvalidWager => "Wager is outside of the allowed range for this question.",
+            GameError::MissingWagers => "All contestants must submit a wager before the question is shown.",
+            GameError::TeamNotFound => "Could not find the team.",
+            GameError::InvalidQuestionChoices => "Multiple choice questions must have at least 2 different choices, one of which is the correct answer.",
+            GameError::ChoiceNotFound => "Could not find the choice for this question.",
+            GameError::NothingToUndo => "There are no actions left to undo.",
+            GameError::PlayerBanned => "You have been banned from this game.",
+            GameError::LastHost => "The game must always have at least one Host.",
+            GameError::GamePaused => "The game is paused.",
+            GameError::ServerShuttingDown => "The server is shutting down.",
+            GameError::BoardNotFound => "Board could not be found.",
+            GameError::InvalidBoardName => "Boards must have a name.",
+            GameError::InvalidCsv => "The CSV could not be read. It must have a header row with round, category, value, question and answer columns.",
+            GameError::InvalidRoundNumber => "The round must be a whole number, starting from 1 without skipping any rounds.",
+            GameError::InvalidQuestionValue => "The value must be a whole number.",
+            GameError::InvalidTimeLimit => "Time limits must be between 1 and 3600 seconds, and the buzz window at most 5000 milliseconds.",
         };
     }
 }
@@ -134,11 +431,98 @@ impl IntoResponse for GameError {
     }
 }
 
+/// Contents of a board in the question bank, when saving a new board or replacing an existing one.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SaveBoardRequest {
+    pub name: String,
+    pub rounds: Vec<Vec<Category>>,
+    #[serde(default)]
+    pub final_round: Option<FinalRound>,
+    #[serde(default)]
+    pub settings: GameSettings,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ListBoardsQuery {
+    /// Only list boards with a question that has this tag
+    pub tag: Option<String>,
+}
+
+/// A board in the question bank without its questions, for listing boards.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BoardSummary {
+    pub board_id: String,
+    pub name: String,
+    pub tags: Vec<String>,
+    pub question_count: usize,
+    /// Total times the board's questions have been played
+    pub times_played: u32,
+    pub last_played: Option<u64>,
+    pub created: u64,
+    pub updated: u64,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ListBoardsResponse {
+    pub boards: Vec<BoardSummary>,
+}
+
+impl IntoResponse for ListBoardsResponse {
+    fn into_response(self) -> axum::response::Response {
+        return (StatusCode::OK, Json(self)).into_response();
+    }
+}
+
+impl IntoResponse for CreateGameRequest {
+    fn into_response(self) -> axum::response::Response {
+        return (StatusCode::OK, Json(self)).into_response();
+    }
+}
+
+/// A problem with an imported CSV. Rows are numbered as in a spreadsheet, so the header is row 1.
+/// Problems with the questions as a whole have no row.
+#[derive(Clone, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportRowError {
+    pub row: Option<u64>,
+    pub column: Option<&'static str>,
+    pub error: GameError,
+    pub message: &'static str,
+}
+
+impl ImportRowError {
+    pub fn new(row: Option<u64>, column: Option<&'static str>, error: GameError) -> ImportRowError {
+        return ImportRowError {
+            row,
+            column,
+            message: error.get_message(),
+            error,
+        };
+    }
+}
+
+#[derive(Clone, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportErrorResponse {
+    pub errors: Vec<ImportRowError>,
+}
+
+impl IntoResponse for ImportErrorResponse {
+    fn into_response(self) -> axum::response::Response {
+        return (StatusCode::BAD_REQUEST, Json(self)).into_response();
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct StatsResponse {
     pub games_count: usize,
     pub version: &'static str,
+    /// Characters game IDs are made from, so clients can check an ID before trying to join
+    pub game_id_alphabet: String,
 }
 
 impl IntoResponse for StatsResponse {
@@ -146,3 +530,26 @@ impl IntoResponse for StatsResponse {
         return (StatusCode::OK, Json(self)).into_response();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_kind_matches_its_type() {
+        let requests = [
+            UpdateGameRequest::StartGame,
+            UpdateGameRequest::PickQuestion {
+                question_id: "q1".to_string(),
+            },
+            UpdateGameRequest::SubmitAnswer {
+                answer: "A secret answer".to_string(),
+            },
+            UpdateGameRequest::ResumeGame,
+        ];
+        for request in requests {
+            let json = serde_json::to_value(&request).unwrap();
+            assert_eq!(json["type"], request.kind());
+        }
+    }
+}
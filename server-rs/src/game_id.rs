@@ -0,0 +1,146 @@
+use rand::seq::SliceRandom;
+
+use crate::config::ServerConfig;
+
+/// Words game IDs must never contain, on top of any added in the config
+const BLOCKLIST: [&str; 20] = [
+    "ASS", "BUM", "CUM", "FAG", "FCK", "FUK", "FUCK", "SHIT", "CUNT", "COCK", "DICK", "PISS",
+    "TWAT", "SLUT", "WHORE", "NAZI", "RAPE", "PORN", "SEX", "TIT",
+];
+
+/// Number of times to try generating an ID before giving up
+const MAX_ATTEMPTS: usize = 20;
+
+/// Generates random game IDs from the configured alphabet, avoiding blocked words.
+pub struct GameIdGenerator<'a> {
+    config: &'a ServerConfig,
+    alphabet: Vec<char>,
+    attempts: usize,
+}
+
+impl<'a> GameIdGenerator<'a> {
+    pub fn new(config: &'a ServerConfig) -> GameIdGenerator<'a> {
+        return GameIdGenerator {
+            config,
+            alphabet: config.game_id_alphabet.chars().collect(),
+            attempts: 0,
+        };
+    }
+
+    /// Number of different IDs that can be generated, ignoring the blocklist.
+    pub fn keyspace_size(config: &ServerConfig) -> f64 {
+        return (config.game_id_alphabet.chars().count() as f64).powi(config.game_id_length as i32);
+    }
+
+    fn is_blocked(&self, id: &str) -> bool {
+        return BLOCKLIST
+            .iter()
+            .copied()
+            .chain(self.config.game_id_blocklist.iter().map(|x| x.as_str()))
+            .any(|word| id.contains(&word.to_ascii_uppercase()));
+    }
+}
+
+impl Iterator for GameIdGenerator<'_> {
+    type Item = String;
+
+    /// The next candidate ID, or `None` once too many attempts have been made.
+    fn next(&mut self) -> Option<String> {
+        let mut rng = rand::thread_rng();
+        while self.attempts < MAX_ATTEMPTS {
+            self.attempts += 1;
+            let id: String = (0..self.config.game_id_length)
+                .map(|_x| self.alphabet.choose(&mut rng).unwrap_or(&'A'))
+                .collect();
+            if !self.is_blocked(&id) {
+                return Some(id);
+            }
+        }
+        return None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        actions::GameError,
+        boards::MemoryBoardStore,
+        dto::CreateGameRequest,
+        models::{AppState, Category, Question, QuestionKind},
+        store::MemoryGameStore,
+    };
+
+    fn new_config(alphabet: &str, length: usize, blocklist: &[&str]) -> ServerConfig {
+        return ServerConfig {
+            game_id_alphabet: alphabet.to_string(),
+            game_id_length: length,
+            game_id_blocklist: blocklist.iter().map(|x| x.to_string()).collect(),
+            ..ServerConfig::default()
+        };
+    }
+
+    #[test]
+    fn ids_use_the_configured_alphabet_and_length() {
+        let config = new_config("XYZ", 6, &[]);
+        for id in GameIdGenerator::new(&config) {
+            assert_eq!(id.len(), 6);
+            assert!(id.chars().all(|x| "XYZ".contains(x)));
+        }
+    }
+
+    #[test]
+    fn blocked_words_are_never_generated() {
+        // Either letter on its own is fine, but together they are blocked
+        let config = new_config("AB", 2, &["ab", "BA"]);
+        for id in GameIdGenerator::new(&config).take(100) {
+            assert!(id == "AA" || id == "BB", "{id} should have been blocked");
+        }
+
+        let config = new_config("AS", 3, &["A", "S"]);
+        assert_eq!(GameIdGenerator::new(&config).next(), None);
+    }
+
+    #[tokio::test]
+    async fn games_in_use_are_never_replaced() {
+        let state = AppState::new(
+            new_config("AB", 1, &[]),
+            Arc::new(MemoryGameStore),
+            Arc::new(MemoryBoardStore),
+        );
+        let request = || CreateGameRequest {
+            rounds: vec![vec![Category {
+                category_id: "c1".to_string(),
+                name: "Category".to_string(),
+                questions: vec![Question {
+                    question_id: "q1".to_string(),
+                    detail: "Question".to_string(),
+                    correct_answer: "Answer".to_string(),
+                    value: 100,
+                    answered: false,
+                    daily_double: false,
+                    alternate_answers: Vec::new(),
+                    kind: QuestionKind::FreeResponse,
+                    choices: Vec::new(),
+                    tags: Vec::new(),
+                }],
+            }]],
+            final_round: None,
+            settings: None,
+            board_id: None,
+        };
+
+        let first = state.clone().create_game(request()).unwrap();
+        let second = state.clone().create_game(request()).unwrap();
+        assert_ne!(first.game.id, second.game.id);
+
+        let result = state.clone().create_game(request());
+        assert!(matches!(result, Err(GameError::FailedToCreateGame)));
+        for response in [first, second] {
+            let game_entry = state.games.get(&response.game.id).unwrap();
+            assert_eq!(game_entry.auth.host_token, response.host_token);
+        }
+    }
+}
@@ -0,0 +1,111 @@
+/// Words ignored at the start of an answer, so "The Beatles" matches "Beatles".
+const ARTICLES: [&str; 3] = ["a", "an", "the"];
+
+/// Check whether a typed answer matches any of the accepted answers, ignoring case, punctuation
+/// and leading articles, and allowing a few typos in longer answers.
+pub fn is_match<'a>(answer: &str, accepted: impl IntoIterator<Item = &'a str>) -> bool {
+    let answer = normalize(answer);
+    if answer.is_empty() {
+        return false;
+    }
+
+    return accepted.into_iter().map(normalize).any(|expected| {
+        !expected.is_empty() && levenshtein(&answer, &expected) <= tolerance(&expected)
+    });
+}
+
+fn normalize(answer: &str) -> String {
+    let cleaned: String = answer
+        .to_lowercase()
+        .chars()
+        .map(|x| if x.is_alphanumeric() { x } else { ' ' })
+        .collect();
+
+    let mut words: Vec<&str> = cleaned.split_whitespace().collect();
+    if words.len() > 1 && ARTICLES.contains(&words[0]) {
+        words.remove(0);
+    }
+    return words.join(" ");
+}
+
+/// Number of typos allowed, which grows with the length of the answer. Short answers must be exact,
+/// otherwise "cat" would match "car".
+fn tolerance(expected: &str) -> usize {
+    return match expected.chars().count() {
+        0..=3 => 0,
+        4..=7 => 1,
+        x => (x / 5).min(3),
+    };
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + if a_char == *b_char { 0 } else { 1 };
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+
+    return previous[b.len()];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(answer: &str, accepted: &[&str]) -> bool {
+        return is_match(answer, accepted.iter().copied());
+    }
+
+    #[test]
+    fn ignores_case_and_punctuation() {
+        assert!(matches("new york", &["New York"]));
+        assert!(matches("New-York!", &["New York"]));
+        assert!(matches("  NEW   YORK ", &["New York"]));
+        assert!(matches("AC/DC", &["AC DC"]));
+    }
+
+    #[test]
+    fn ignores_leading_articles() {
+        assert!(matches("Beatles", &["The Beatles"]));
+        assert!(matches("the beatles", &["Beatles"]));
+        assert!(matches("an apple", &["Apple"]));
+        // An article on its own is the whole answer
+        assert!(matches("The", &["The"]));
+        assert!(!matches("The", &["The Who"]));
+    }
+
+    #[test]
+    fn allows_typos_in_longer_answers() {
+        assert!(matches("Missisippi", &["Mississippi"]));
+        assert!(matches("Einstain", &["Einstein"]));
+        assert!(matches("Shakespear", &["Shakespeare"]));
+        assert!(!matches("Mozart", &["Einstein"]));
+    }
+
+    #[test]
+    fn short_answers_must_be_exact() {
+        assert!(matches("Cat", &["cat"]));
+        assert!(!matches("car", &["cat"]));
+        assert!(!matches("1", &["2"]));
+    }
+
+    #[test]
+    fn accepts_alternate_answers() {
+        assert!(matches("USA", &["United States", "USA", "America"]));
+        assert!(matches("america", &["United States", "USA", "America"]));
+        assert!(!matches("Canada", &["United States", "USA", "America"]));
+    }
+
+    #[test]
+    fn empty_answers_never_match() {
+        assert!(!matches("", &["Anything"]));
+        assert!(!matches("?!", &["Anything"]));
+        assert!(!matches("", &[""]));
+    }
+}
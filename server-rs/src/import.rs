@@ -0,0 +1,305 @@
+use std::collections::BTreeMap;
+
+use csv::{ReaderBuilder, Trim};
+
+use crate::{
+    actions::{self, GameError},
+    dto::{CreateGameRequest, ImportRowError},
+    models::{Category, Question, QuestionKind},
+};
+
+const ROUND: &str = "round";
+const CATEGORY: &str = "category";
+const VALUE: &str = "value";
+const QUESTION: &str = "question";
+const ANSWER: &str = "answer";
+
+/// Build a game from a CSV with a row for each question, reporting every row with a problem.
+pub fn import_csv(csv: &str) -> Result<CreateGameRequest, Vec<ImportRowError>> {
+    // Spreadsheets often start their CSV exports with a byte order mark
+    let csv = csv.trim_start_matches('\u{feff}');
+    let mut reader = ReaderBuilder::new()
+        .trim(Trim::All)
+        .flexible(true)
+        .from_reader(csv.as_bytes());
+
+    // Columns can be in any order, and any other columns are ignored
+    let header_error = vec![ImportRowError::new(Some(1), None, GameError::InvalidCsv)];
+    let headers = match reader.headers() {
+        Ok(x) => x.clone(),
+        Err(_) => return Err(header_error),
+    };
+    let column = |name: &str| headers.iter().position(|x| x.eq_ignore_ascii_case(name));
+    let (round_column, category_column, value_column, question_column, answer_column) = match (
+        column(ROUND),
+        column(CATEGORY),
+        column(VALUE),
+        column(QUESTION),
+        column(ANSWER),
+    ) {
+        (Some(a), Some(b), Some(c), Some(d), Some(e)) => (a, b, c, d, e),
+        _ => return Err(header_error),
+    };
+
+    // Rounds in order of their number, each with its categories in the order they first appear
+    let mut rounds: BTreeMap<u64, Vec<Category>> = BTreeMap::new();
+    // The first row of each round, to point at if the round is numbered wrong
+    let mut round_rows: BTreeMap<u64, u64> = BTreeMap::new();
+    let mut errors = Vec::new();
+    for record in reader.records() {
+        let record = match record {
+            Ok(x) => x,
+            Err(e) => {
+                let row = e.position().map(|x| x.record() + 1);
+                errors.push(ImportRowError::new(row, None, GameError::InvalidCsv));
+                continue;
+            }
+        };
+        // Skip rows left empty between sections of the spreadsheet
+        if record.iter().all(|x| x.is_empty()) {
+            continue;
+        }
+
+        // Count records rather than lines, as a quoted cell can span several lines but is still one
+        // row in the spreadsheet
+        let row = record
+            .position()
+            .map(|x| x.record() + 1)
+            .unwrap_or_default();
+        let cell = |index: usize| record.get(index).unwrap_or_default();
+        let mut row_error = |column: &'static str, error: GameError| {
+            errors.push(ImportRowError::new(Some(row), Some(column), error));
+        };
+
+        let round = match cell(round_column).parse::<u64>() {
+            Ok(x) if x >= 1 => x,
+            _ => {
+                row_error(ROUND, GameError::InvalidRoundNumber);
+                continue;
+            }
+        };
+        round_rows.entry(round).or_insert(row);
+        let value = match cell(value_column).parse::<isize>() {
+            Ok(x) => x,
+            Err(_) => {
+                row_error(VALUE, GameError::InvalidQuestionValue);
+                continue;
+            }
+        };
+        let question = Question {
+            question_id: format!("q{row}"),
+            detail: cell(question_column).to_string(),
+            correct_answer: cell(answer_column).to_string(),
+            value,
+            answered: false,
+            daily_double: false,
+            alternate_answers: Vec::new(),
+            kind: QuestionKind::FreeResponse,
+            choices: Vec::new(),
+            tags: Vec::new(),
+        };
+
+        // The same checks as creating a game, but pointing at the cell with the problem
+        let category_name = cell(category_column);
+        if category_name.is_empty() {
+            row_error(CATEGORY, GameError::MissingQuestions);
+            continue;
+        }
+        if !question.is_complete() {
+            let column = if question.detail.is_empty() {
+                QUESTION
+            } else {
+                ANSWER
+            };
+            row_error(column, GameError::MissingQuestions);
+            continue;
+        }
+
+        let categories = rounds.entry(round).or_default();
+        let index = match categories.iter().position(|x| x.name == category_name) {
+            Some(x) => x,
+            None => {
+                categories.push(Category {
+                    category_id: format!("r{round}c{}", categories.len() + 1),
+                    name: category_name.to_string(),
+                    questions: Vec::new(),
+                });
+                categories.len() - 1
+            }
+        };
+        categories[index].questions.push(question);
+    }
+
+    // Rounds must be numbered without gaps, so a typo in the round can't quietly renumber the rest
+    let skipped_round = (1..)
+        .zip(round_rows.iter())
+        .find(|(expected, (round, _))| *round != expected);
+    if let Some((_, (_, row))) = skipped_round {
+        errors.push(ImportRowError::new(
+            Some(*row),
+            Some(ROUND),
+            GameError::InvalidRoundNumber,
+        ));
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let mut rounds: Vec<Vec<Category>> = rounds.into_values().collect();
+    for category in rounds.iter_mut().flatten() {
+        category.questions.sort_by_key(|x| x.value);
+    }
+    let mut final_round = None;
+    if let Err(e) = actions::prepare_questions(&mut rounds, &mut final_round) {
+        return Err(vec![ImportRowError::new(None, None, e)]);
+    }
+
+    return Ok(CreateGameRequest {
+        rounds,
+        final_round,
+        settings: None,
+        board_id: None,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Each problem as its row, column and kind of error.
+    fn import_errors(csv: &str) -> Vec<(Option<u64>, Option<&'static str>, &'static str)> {
+        return match import_csv(csv) {
+            Ok(_) => panic!("expected the import to fail"),
+            Err(errors) => errors
+                .iter()
+                .map(|x| (x.row, x.column, x.error.kind()))
+                .collect(),
+        };
+    }
+
+    /// Names of each round's categories, with their questions' values.
+    fn board(request: &CreateGameRequest) -> Vec<Vec<(String, Vec<isize>)>> {
+        return request
+            .rounds
+            .iter()
+            .map(|round| {
+                round
+                    .iter()
+                    .map(|x| {
+                        (
+                            x.name.clone(),
+                            x.questions.iter().map(|x| x.value).collect(),
+                        )
+                    })
+                    .collect()
+            })
+            .collect();
+    }
+
+    #[test]
+    fn columns_can_be_in_any_order() {
+        let csv = "Answer,Notes,VALUE,question,Category,round\n\
+                   Paris,easy,100,What is the capital of France?,Geography,1\n";
+        let request = import_csv(csv).unwrap();
+
+        let question = &request.rounds[0][0].questions[0];
+        assert_eq!(request.rounds[0][0].name, "Geography");
+        assert_eq!(question.detail, "What is the capital of France?");
+        assert_eq!(question.correct_answer, "Paris");
+        assert_eq!(question.value, 100);
+    }
+
+    #[test]
+    fn byte_order_mark_is_ignored() {
+        let csv = "\u{feff}round,category,value,question,answer\n\
+                   1,Music,100,Who wrote Yesterday?,The Beatles\n";
+        assert!(import_csv(csv).is_ok());
+    }
+
+    #[test]
+    fn missing_columns_are_reported_on_the_header() {
+        let csv = "round,category,value,question\n1,Music,100,Who wrote Yesterday?\n";
+        assert_eq!(import_errors(csv), [(Some(1), None, "InvalidCsv")]);
+    }
+
+    #[test]
+    fn categories_and_values_are_ordered() {
+        let csv = "round,category,value,question,answer\n\
+                   2,Music,200,Q1,A1\n\
+                   1,Geography,300,Q2,A2\n\
+                   1,History,100,Q3,A3\n\
+                   1,Geography,100,Q4,A4\n\
+                   2,Music,100,Q5,A5\n";
+        let request = import_csv(csv).unwrap();
+
+        assert_eq!(
+            board(&request),
+            [
+                vec![
+                    ("Geography".to_string(), vec![100, 300]),
+                    ("History".to_string(), vec![100]),
+                ],
+                vec![("Music".to_string(), vec![100, 200])],
+            ]
+        );
+    }
+
+    #[test]
+    fn problems_are_reported_by_row_and_column() {
+        let csv = "round,category,value,question,answer\n\
+                   one,Music,100,Q1,A1\n\
+                   1,Music,lots,Q2,A2\n\
+                   1,Music,100,Q3,\n\
+                   1,Music,100,,A4\n\
+                   1,,100,Q5,A5\n\
+                   0,Music,100,Q6,A6\n";
+
+        assert_eq!(
+            import_errors(csv),
+            [
+                (Some(2), Some(ROUND), "InvalidRoundNumber"),
+                (Some(3), Some(VALUE), "InvalidQuestionValue"),
+                (Some(4), Some(ANSWER), "MissingQuestions"),
+                (Some(5), Some(QUESTION), "MissingQuestions"),
+                (Some(6), Some(CATEGORY), "MissingQuestions"),
+                (Some(7), Some(ROUND), "InvalidRoundNumber"),
+            ]
+        );
+    }
+
+    #[test]
+    fn blank_rows_are_skipped_but_still_counted() {
+        let csv = "round,category,value,question,answer\n\
+                   1,Music,100,Q1,A1\n\
+                   ,,,,\n\
+                   1,Music,lots,Q2,A2\n";
+        assert_eq!(
+            import_errors(csv),
+            [(Some(4), Some(VALUE), "InvalidQuestionValue")]
+        );
+    }
+
+    #[test]
+    fn cells_with_line_breaks_are_one_row() {
+        let csv = "round,category,value,question,answer\n\
+                   1,Music,100,\"Who wrote\nYesterday?\",The Beatles\n\
+                   1,Music,lots,Q2,A2\n";
+        assert_eq!(
+            import_errors(csv),
+            [(Some(3), Some(VALUE), "InvalidQuestionValue")]
+        );
+    }
+
+    #[test]
+    fn skipped_rounds_are_reported() {
+        let csv = "round,category,value,question,answer\n\
+                   1,Music,100,Q1,A1\n\
+                   3,History,100,Q2,A2\n\
+                   3,History,200,Q3,A3\n";
+        assert_eq!(
+            import_errors(csv),
+            [(Some(3), Some(ROUND), "InvalidRoundNumber")]
+        );
+    }
+}
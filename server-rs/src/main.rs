@@ -1,21 +1,38 @@
 mod actions;
 mod background;
+mod boards;
+mod config;
 mod dto;
+mod game_id;
+mod grading;
+mod import;
+mod metrics;
 mod models;
+mod shutdown;
+mod store;
 mod ws;
 mod util;
 
-use std::{net::SocketAddr, sync::Arc};
+use std::{borrow::Borrow, net::SocketAddr, sync::Arc};
 
 use actions::GameError;
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware,
+    response::IntoResponse,
     routing::{get, post},
     Json, Router,
 };
-use dashmap::DashMap;
-use dto::{CreateGameRequest, StatsResponse};
+use boards::{Board, BoardStore, FileBoardStore, MemoryBoardStore};
+use config::ServerConfig;
+use dto::{
+    CreateGameRequest, CreateGameResponse, GameOverview, GetGameQuery, ImportErrorResponse,
+    ListBoardsQuery, ListBoardsResponse, SaveBoardRequest, StatsResponse, UpdateGameRequest,
+};
 use models::{AppState, Game};
+use store::{FileGameStore, GameStore, MemoryGameStore};
+use util::bearer_token;
 
 use tower_http::{
     services::{ServeDir, ServeFile},
@@ -38,62 +55,274 @@ async fn main() {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    let state = AppState {
-        games: Arc::new(DashMap::new()),
+    let config = match ServerConfig::load() {
+        Ok(x) => x,
+        Err(e) => {
+            tracing::error!("Failed to load the server config: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    // Games are only persisted to disk when a data directory is configured
+    let store: Arc<dyn GameStore> = match &config.data_dir {
+        Some(dir) => {
+            tracing::info!("persisting games to {dir:?}");
+            Arc::new(FileGameStore::new(dir).expect("failed to open the data directory"))
+        }
+        None => Arc::new(MemoryGameStore),
     };
+    let board_store: Arc<dyn BoardStore> = match &config.data_dir {
+        Some(dir) => Arc::new(
+            FileBoardStore::new(dir.join("boards")).expect("failed to open the boards directory"),
+        ),
+        None => Arc::new(MemoryBoardStore),
+    };
+
+    let state = AppState::new(config.clone(), store, board_store);
+    state.restore_games();
+    state.restore_boards();
 
     background::start_cleanup_old_games(state.clone());
 
-    let serve_dir =
-        ServeDir::new("wwwroot").not_found_service(ServeFile::new("wwwroot/index.html"));
+    let serve_dir = ServeDir::new(&config.static_dir)
+        .not_found_service(ServeFile::new(config.static_dir.join("index.html")));
 
     let app = Router::new()
         .nest_service("/", serve_dir.clone())
         .route("/api/stats", get(get_stats))
+        .route("/metrics", get(get_metrics))
         .route("/api/games", post(create_game))
         .route(
             "/api/stream/games/:game_id/:role/:username",
             get(ws::join_game),
         )
         .route("/api/games/:game_id", get(get_game))
+        .route("/api/games/:game_id/actions", post(update_game))
+        .route("/api/import/csv", post(import_csv))
+        .route("/api/boards", get(list_boards).post(create_board))
+        .route(
+            "/api/boards/:board_id",
+            get(get_board).put(update_board).delete(delete_board),
+        )
         .fallback_service(serve_dir)
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            metrics::track_http_requests,
+        ))
         .layer(
             TraceLayer::new_for_http()
                 .make_span_with(DefaultMakeSpan::default().include_headers(true)),
         )
-        .with_state(state);
+        .with_state(state.clone());
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
+    let listener = tokio::net::TcpListener::bind(&config.bind_address)
+        .await
+        .unwrap();
     tracing::info!("listening on {}", listener.local_addr().unwrap());
     tracing::info!("version: {}", VERSION);
-    axum::serve(
+    let server = axum::serve(
         listener,
         app.into_make_service_with_connect_info::<SocketAddr>(),
     )
-    .await
-    .unwrap();
+    .with_graceful_shutdown(shutdown::shutdown_signal(state.clone()));
+
+    // Once shutting down, wait for requests in flight and every WebSocket to close, but not forever
+    let graceful = async {
+        server.await.unwrap();
+        state.shutdown.closed().await;
+    };
+    tokio::select! {
+        _ = graceful => tracing::info!("Shut down gracefully"),
+        _ = state.shutdown_timeout() => tracing::warn!(
+            "{} connections were still open when the shutdown timed out",
+            state.shutdown.receiver_count()
+        ),
+    }
+
+    // Games saved while shutting down are written in the background, so wait for them to finish
+    let (store, board_store) = (state.store.clone(), state.board_store.clone());
+    let _ = tokio::task::spawn_blocking(move || {
+        store.flush();
+        board_store.flush();
+    })
+    .await;
 }
 
 async fn get_stats(State(state): State<AppState>) -> StatsResponse {
     return StatsResponse {
         games_count: state.games.len(),
         version: VERSION,
+        game_id_alphabet: state.config.game_id_alphabet.clone(),
     };
 }
 
+async fn get_metrics(State(state): State<AppState>) -> impl IntoResponse {
+    return (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.render(&state),
+    );
+}
+
 async fn create_game(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Json(new_game): Json<CreateGameRequest>,
-) -> Result<Game, GameError> {
-    return state.create_game(new_game);
+) -> Result<CreateGameResponse, GameError> {
+    let metrics = state.metrics.clone();
+    // Creating a game from a board would show the board to the Host, so needs the same access
+    if new_game.board_id.is_some() {
+        state
+            .authorize_boards(&headers)
+            .inspect_err(|e| metrics.record_error(e))?;
+    }
+    return state
+        .create_game(new_game)
+        .inspect_err(|e| metrics.record_error(e));
 }
 
 async fn get_game(
     Path(game_id): Path<String>,
+    Query(query): Query<GetGameQuery>,
     State(state): State<AppState>,
 ) -> Result<Game, GameError> {
-    match state.games.get(&game_id.to_ascii_uppercase()) {
-        Some(x) => return Ok(x.value().game.clone()),
-        None => return Err(GameError::GameNotFound),
+    let game_entry = match state.games.get(&game_id.to_ascii_uppercase()) {
+        Some(x) => x,
+        None => {
+            state.metrics.record_error(&GameError::GameNotFound);
+            return Err(GameError::GameNotFound);
+        }
+    };
+
+    if query.token.is_some_and(|token| game_entry.is_host(&token)) {
+        return Ok(game_entry.game.clone());
     }
+    return Ok(game_entry.game.redacted());
+}
+
+/// Apply an `UpdateGameRequest` over HTTP, for clients that can't hold a WebSocket open. The caller
+/// is identified by a player token, or the host token to act as a Host, in the `Authorization`
+/// header.
+async fn update_game(
+    Path(game_id): Path<String>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<UpdateGameRequest>,
+) -> Result<GameOverview, GameError> {
+    let token = match bearer_token(&headers) {
+        Some(x) => x.to_string(),
+        None => {
+            state.metrics.record_error(&GameError::InvalidToken);
+            return Err(GameError::InvalidToken);
+        }
+    };
+
+    let mut game_entry = match state.games.get_mut(&game_id.to_ascii_uppercase()) {
+        Some(x) => x,
+        None => {
+            state.metrics.record_error(&GameError::GameNotFound);
+            return Err(GameError::GameNotFound);
+        }
+    };
+
+    let token = if token == game_entry.auth.host_token {
+        match game_entry.host_player_token() {
+            Some(x) => x.clone(),
+            None => {
+                state.metrics.record_error(&GameError::PlayerNotFound);
+                return Err(GameError::PlayerNotFound);
+            }
+        }
+    } else {
+        token
+    };
+
+    state.process_game_request(&mut game_entry, token.clone(), request)?;
+
+    let mut update: GameOverview = game_entry.game.borrow().into();
+    if !game_entry.is_host(&token) {
+        update.state = update.state.redacted();
+    }
+    return Ok(update);
+}
+
+/// Turn a CSV of questions into a `CreateGameRequest`, which can then be used to create a game or
+/// save a board.
+async fn import_csv(
+    State(state): State<AppState>,
+    body: String,
+) -> Result<CreateGameRequest, ImportErrorResponse> {
+    return import::import_csv(&body).map_err(|errors| {
+        // Count each failed import once, however many rows it has problems with
+        if let Some(x) = errors.first() {
+            state.metrics.record_error(&x.error);
+        }
+        ImportErrorResponse { errors }
+    });
+}
+
+async fn list_boards(
+    Query(query): Query<ListBoardsQuery>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<ListBoardsResponse, GameError> {
+    state
+        .authorize_boards(&headers)
+        .inspect_err(|e| state.metrics.record_error(e))?;
+    return Ok(ListBoardsResponse {
+        boards: state.list_boards(query.tag),
+    });
+}
+
+async fn get_board(
+    Path(board_id): Path<String>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Board, GameError> {
+    state
+        .authorize_boards(&headers)
+        .inspect_err(|e| state.metrics.record_error(e))?;
+    return state
+        .get_board(&board_id)
+        .inspect_err(|e| state.metrics.record_error(e));
+}
+
+async fn create_board(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<SaveBoardRequest>,
+) -> Result<Board, GameError> {
+    state
+        .authorize_boards(&headers)
+        .inspect_err(|e| state.metrics.record_error(e))?;
+    return state
+        .create_board(request)
+        .inspect_err(|e| state.metrics.record_error(e));
+}
+
+async fn update_board(
+    Path(board_id): Path<String>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<SaveBoardRequest>,
+) -> Result<Board, GameError> {
+    state
+        .authorize_boards(&headers)
+        .inspect_err(|e| state.metrics.record_error(e))?;
+    return state
+        .update_board(&board_id, request)
+        .inspect_err(|e| state.metrics.record_error(e));
+}
+
+async fn delete_board(
+    Path(board_id): Path<String>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<StatusCode, GameError> {
+    state
+        .authorize_boards(&headers)
+        .inspect_err(|e| state.metrics.record_error(e))?;
+    state
+        .delete_board(&board_id)
+        .inspect_err(|e| state.metrics.record_error(e))?;
+    return Ok(StatusCode::NO_CONTENT);
 }
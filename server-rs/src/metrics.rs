@@ -0,0 +1,294 @@
+use axum::{
+    extract::{MatchedPath, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use prometheus::{
+    core::Collector, Encoder, Gauge, HistogramOpts, HistogramVec, IntCounter, IntCounterVec,
+    IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use std::collections::HashMap;
+
+use tokio::time::Instant;
+
+use crate::{
+    actions::GameError,
+    game_id::GameIdGenerator,
+    models::{AppState, PlayerRole},
+};
+
+/// Metrics exposed in the Prometheus text format on `/metrics`.
+pub struct Metrics {
+    registry: Registry,
+    games: IntGaugeVec,
+    connections: IntGaugeVec,
+    pub game_requests: IntCounterVec,
+    pub game_request_duration: HistogramVec,
+    game_errors: IntCounterVec,
+    pub lagged_clients: IntCounter,
+    pub games_cleaned_up: IntCounter,
+    pub game_id_collisions: IntCounter,
+    game_id_keyspace_used: Gauge,
+    http_request_duration: HistogramVec,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        let registry = Registry::new_custom(Some("trivia".to_string()), None).unwrap();
+
+        let games =
+            IntGaugeVec::new(Opts::new("games", "Active games by state"), &["state"]).unwrap();
+        let connections = IntGaugeVec::new(
+            Opts::new("websocket_connections", "Connected WebSockets by role"),
+            &["role"],
+        )
+        .unwrap();
+        let game_requests = IntCounterVec::new(
+            Opts::new("game_requests_total", "Game requests processed by type"),
+            &["type"],
+        )
+        .unwrap();
+        let game_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "game_request_duration_seconds",
+                "Time taken to process game requests by type",
+            ),
+            &["type"],
+        )
+        .unwrap();
+        let game_errors = IntCounterVec::new(
+            Opts::new("game_errors_total", "Game errors by kind"),
+            &["error"],
+        )
+        .unwrap();
+        let lagged_clients = IntCounter::new(
+            "broadcast_lag_total",
+            "Times a client fell behind its game's channel and had to be resynced",
+        )
+        .unwrap();
+        let games_cleaned_up = IntCounter::new(
+            "games_cleaned_up_total",
+            "Stale games removed by the background task",
+        )
+        .unwrap();
+        let game_id_collisions = IntCounter::new(
+            "game_id_collisions_total",
+            "Generated game IDs that were already in use",
+        )
+        .unwrap();
+        let game_id_keyspace_used = Gauge::new(
+            "game_id_keyspace_used_ratio",
+            "Fraction of possible game IDs used by active games",
+        )
+        .unwrap();
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time taken to respond to HTTP requests",
+            ),
+            &["method", "path", "status"],
+        )
+        .unwrap();
+
+        registry.register(Box::new(games.clone())).unwrap();
+        registry.register(Box::new(connections.clone())).unwrap();
+        registry.register(Box::new(game_requests.clone())).unwrap();
+        registry
+            .register(Box::new(game_request_duration.clone()))
+            .unwrap();
+        registry.register(Box::new(game_errors.clone())).unwrap();
+        registry.register(Box::new(lagged_clients.clone())).unwrap();
+        registry
+            .register(Box::new(games_cleaned_up.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(game_id_collisions.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(game_id_keyspace_used.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(http_request_duration.clone()))
+            .unwrap();
+
+        return Metrics {
+            registry,
+            games,
+            connections,
+            game_requests,
+            game_request_duration,
+            game_errors,
+            lagged_clients,
+            games_cleaned_up,
+            game_id_collisions,
+            game_id_keyspace_used,
+            http_request_duration,
+        };
+    }
+
+    pub fn record_error(&self, error: &GameError) {
+        self.game_errors.with_label_values(&[error.kind()]).inc();
+    }
+
+    /// Encode all metrics, counting the games in each state as of now.
+    pub fn render(&self, state: &AppState) -> String {
+        // Count first and then set each gauge, so a scrape at the same time never sees a partial count
+        let mut counts: HashMap<String, i64> = HashMap::new();
+        for game_entry in state.games.iter() {
+            *counts
+                .entry(game_entry.game.state.kind().to_string())
+                .or_default() += 1;
+        }
+        // States that no longer have any games go down to zero
+        for family in Collector::collect(&self.games) {
+            for metric in family.get_metric() {
+                for label in metric.get_label() {
+                    counts.entry(label.get_value().to_string()).or_default();
+                }
+            }
+        }
+        for (kind, count) in counts {
+            self.games.with_label_values(&[&kind]).set(count);
+        }
+        self.game_id_keyspace_used
+            .set(state.games.len() as f64 / GameIdGenerator::keyspace_size(&state.config));
+
+        let mut buffer = Vec::new();
+        if let Err(e) = TextEncoder::new().encode(&self.registry.gather(), &mut buffer) {
+            tracing::error!("Failed to encode metrics: {e}");
+        }
+        return String::from_utf8(buffer).unwrap_or_default();
+    }
+}
+
+/// Counts a WebSocket connection under its player's current role, for as long as it is kept.
+pub struct TrackedConnection {
+    connections: IntGaugeVec,
+    role: PlayerRole,
+}
+
+impl TrackedConnection {
+    pub fn new(metrics: &Metrics, role: PlayerRole) -> TrackedConnection {
+        metrics.connections.with_label_values(&[role.kind()]).inc();
+        return TrackedConnection {
+            connections: metrics.connections.clone(),
+            role,
+        };
+    }
+
+    /// Move the connection to the player's role, such as after they are promoted or demoted.
+    pub fn set_role(&mut self, role: &PlayerRole) {
+        if *role == self.role {
+            return;
+        }
+        self.connections
+            .with_label_values(&[self.role.kind()])
+            .dec();
+        self.connections.with_label_values(&[role.kind()]).inc();
+        self.role = role.clone();
+    }
+}
+
+impl Drop for TrackedConnection {
+    fn drop(&mut self) {
+        self.connections
+            .with_label_values(&[self.role.kind()])
+            .dec();
+    }
+}
+
+/// Middleware recording how long each HTTP request takes, labelled by its route.
+pub async fn track_http_requests(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let start = Instant::now();
+    let method = request.method().to_string();
+    // Label by route rather than the actual path, so there is a fixed set of labels
+    let path = match request.extensions().get::<MatchedPath>() {
+        Some(x) => x.as_str().to_string(),
+        None => "unmatched".to_string(),
+    };
+
+    let response = next.run(request).await;
+
+    state
+        .metrics
+        .http_request_duration
+        .with_label_values(&[&method, &path, response.status().as_str()])
+        .observe(start.elapsed().as_secs_f64());
+    return response;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        boards::MemoryBoardStore,
+        config::ServerConfig,
+        dto::CreateGameRequest,
+        models::{Game, GameEntry, GameState},
+        store::MemoryGameStore,
+    };
+
+    fn encode(metrics: &Metrics) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&metrics.registry.gather(), &mut buffer)
+            .unwrap();
+        return String::from_utf8(buffer).unwrap();
+    }
+
+    #[test]
+    fn errors_are_labelled_by_kind() {
+        let metrics = Metrics::new();
+        metrics.record_error(&GameError::InvalidToken);
+        metrics.record_error(&GameError::InvalidToken);
+
+        assert!(encode(&metrics).contains("trivia_game_errors_total{error=\"InvalidToken\"} 2"));
+    }
+
+    #[test]
+    fn games_are_counted_by_their_current_state() {
+        let state = AppState::new(
+            ServerConfig::default(),
+            Arc::new(MemoryGameStore),
+            Arc::new(MemoryBoardStore),
+        );
+        let game: Game = CreateGameRequest {
+            rounds: Vec::new(),
+            final_round: None,
+            settings: None,
+            board_id: None,
+        }
+        .into();
+        state
+            .games
+            .insert(game.id.clone(), GameEntry::new(game.clone(), 16));
+
+        let rendered = state.metrics.render(&state);
+        assert!(rendered.contains("trivia_games{state=\"WaitingToStart\"} 1"));
+
+        state.games.get_mut(&game.id).unwrap().game.state = GameState::PickAQuestion;
+        let rendered = state.metrics.render(&state);
+        assert!(rendered.contains("trivia_games{state=\"WaitingToStart\"} 0"));
+        assert!(rendered.contains("trivia_games{state=\"PickAQuestion\"} 1"));
+    }
+
+    #[test]
+    fn connections_follow_the_players_role() {
+        let metrics = Metrics::new();
+        let mut connection = TrackedConnection::new(&metrics, PlayerRole::Contestant);
+        connection.set_role(&PlayerRole::Host);
+
+        let rendered = encode(&metrics);
+        assert!(rendered.contains("trivia_websocket_connections{role=\"Contestant\"} 0"));
+        assert!(rendered.contains("trivia_websocket_connections{role=\"Host\"} 1"));
+
+        drop(connection);
+        assert!(encode(&metrics).contains("trivia_websocket_connections{role=\"Host\"} 0"));
+    }
+}
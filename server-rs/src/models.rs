@@ -1,22 +1,213 @@
-use std::sync::Arc;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+};
 
 use axum::{http::StatusCode, response::IntoResponse, Json};
 use dashmap::DashMap;
 use serde::{Deserialize, Serialize};
 use tokio::time::Instant;
 
-use crate::dto::GameMessage;
+use crate::{
+    boards::{Board, BoardStore},
+    config::ServerConfig,
+    dto::{GameMessage, SequencedMessage},
+    metrics::Metrics,
+    store::GameStore,
+};
+
+/// Number of recent messages kept for each game, so reconnecting clients can catch up
+const HISTORY_SIZE: usize = 256;
+/// Number of actions that can be undone in a row
+const UNDO_HISTORY_SIZE: usize = 20;
+/// Longest buzz or answer time limit a game can have
+const MAX_TIME_LIMIT_SECS: u64 = 3600;
+/// Longest buzz window a game can have
+const MAX_BUZZ_WINDOW_MS: u64 = 5000;
 
 #[derive(Clone)]
 pub struct AppState {
     pub games: Arc<DashMap<String, GameEntry>>,
+    pub store: Arc<dyn GameStore>,
+    /// Saved boards in the question bank, by board ID
+    pub boards: Arc<DashMap<String, Board>>,
+    pub board_store: Arc<dyn BoardStore>,
+    pub config: Arc<ServerConfig>,
+    pub metrics: Arc<Metrics>,
+    /// Set once the server starts shutting down. Each WebSocket holds a receiver until it closes,
+    /// so shutdown can wait for them all to finish.
+    pub shutdown: Arc<tokio::sync::watch::Sender<bool>>,
+}
+
+impl AppState {
+    pub fn new(
+        config: ServerConfig,
+        store: Arc<dyn GameStore>,
+        board_store: Arc<dyn BoardStore>,
+    ) -> AppState {
+        return AppState {
+            games: Arc::new(DashMap::new()),
+            store,
+            boards: Arc::new(DashMap::new()),
+            board_store,
+            config: Arc::new(config),
+            metrics: Arc::new(Metrics::new()),
+            shutdown: Arc::new(tokio::sync::watch::channel(false).0),
+        };
+    }
 }
 
 pub struct GameEntry {
     pub game: Game,
+    pub auth: GameAuth,
     pub last_updated: Instant,
-    pub sender: tokio::sync::broadcast::Sender<GameMessage>,
-    pub receiver: tokio::sync::broadcast::Receiver<GameMessage>,
+    pub sender: tokio::sync::broadcast::Sender<SequencedMessage>,
+    pub receiver: tokio::sync::broadcast::Receiver<SequencedMessage>,
+    /// Sequence number of the last message broadcast
+    pub seq: u64,
+    /// The most recent messages broadcast, oldest first
+    pub history: VecDeque<SequencedMessage>,
+    /// The game as it was before each of the most recent actions, oldest first
+    pub undo_history: VecDeque<Game>,
+    /// Notifies the game's timer task whenever `game.timer` changes
+    pub timer: tokio::sync::watch::Sender<Option<GameTimer>>,
+    /// Buzzes received during the current buzz window
+    pub buzzes: Vec<Buzz>,
+    /// Round trip time in milliseconds to each player, measured from the heartbeat pings
+    pub latencies: HashMap<String, u64>,
+}
+
+impl GameEntry {
+    pub fn new(game: Game, channel_capacity: usize) -> GameEntry {
+        let (sender, receiver) = tokio::sync::broadcast::channel(channel_capacity);
+        let (timer, _) = tokio::sync::watch::channel(game.timer.clone());
+        return GameEntry {
+            game,
+            auth: GameAuth::default(),
+            sender,
+            receiver,
+            last_updated: Instant::now(),
+            seq: 0,
+            history: VecDeque::with_capacity(HISTORY_SIZE),
+            undo_history: VecDeque::new(),
+            timer,
+            buzzes: Vec::new(),
+            latencies: HashMap::new(),
+        };
+    }
+
+    /// Number a message and send it to everyone in the game, remembering it for clients that
+    /// reconnect.
+    pub fn broadcast(&mut self, message: GameMessage) {
+        self.seq += 1;
+        let message = SequencedMessage {
+            seq: self.seq,
+            message,
+        };
+        if self.history.len() >= HISTORY_SIZE {
+            self.history.pop_front();
+        }
+        self.history.push_back(message.clone());
+        let _ = self.sender.send(message);
+    }
+
+    /// All messages broadcast after `since`, or `None` if some of them have already been forgotten.
+    pub fn messages_since(&self, since: u64) -> Option<Vec<SequencedMessage>> {
+        let oldest = self.history.front().map_or(self.seq + 1, |x| x.seq);
+        if since > self.seq || (since < self.seq && oldest > since + 1) {
+            return None;
+        }
+        return Some(
+            self.history
+                .iter()
+                .filter(|x| x.seq > since)
+                .cloned()
+                .collect(),
+        );
+    }
+
+    /// Remember the game as it was before an action, so the action can be undone.
+    pub fn remember_for_undo(&mut self, game: Game) {
+        if self.undo_history.len() >= UNDO_HISTORY_SIZE {
+            self.undo_history.pop_front();
+        }
+        self.undo_history.push_back(game);
+    }
+
+    pub fn set_timer(&mut self, timer: Option<GameTimer>) {
+        self.game.timer = timer.clone();
+        self.timer.send_replace(timer);
+    }
+
+    /// Whether the token is the game's host token or belongs to a player who is a Host.
+    pub fn is_host(&self, token: &str) -> bool {
+        return token == self.auth.host_token
+            || self
+                .auth
+                .get_username(token)
+                .is_some_and(|username| self.is_host_username(username));
+    }
+
+    /// Token of the first Host to join the game, so the host token can act on their behalf. If they
+    /// are no longer a Host, another Host is used instead.
+    pub fn host_player_token(&self) -> Option<&String> {
+        let host_username = match &self.auth.host_username {
+            Some(x) if self.is_host_username(x) => x,
+            _ => {
+                let player = self
+                    .game
+                    .players
+                    .iter()
+                    .find(|x| x.role == PlayerRole::Host)?;
+                &player.username
+            }
+        };
+        return self
+            .auth
+            .player_tokens
+            .iter()
+            .find(|(_, username)| *username == host_username)
+            .map(|(token, _)| token);
+    }
+
+    fn is_host_username(&self, username: &str) -> bool {
+        return self
+            .game
+            .players
+            .iter()
+            .any(|x| x.username == username && x.role == PlayerRole::Host);
+    }
+}
+
+/// Secrets for a game, kept separate from `Game` so they are never sent to clients.
+#[derive(Clone, Serialize, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct GameAuth {
+    pub host_token: String,
+    /// Reconnect tokens issued to players, mapped to the username they belong to
+    pub player_tokens: HashMap<String, String>,
+    /// Usernames banned by the Host, which can't be used to join the game again
+    #[serde(default)]
+    pub banned_usernames: Vec<String>,
+    /// Board the game was created from, kept here so players can't use it to look up the answers
+    #[serde(default)]
+    pub board_id: Option<String>,
+    /// The first Host to join the game, who requests made with the host token are made as
+    #[serde(default)]
+    pub host_username: Option<String>,
+}
+
+impl GameAuth {
+    pub fn get_username(&self, token: &str) -> Option<&String> {
+        return self.player_tokens.get(token);
+    }
+
+    pub fn is_banned(&self, username: &str) -> bool {
+        return self
+            .banned_usernames
+            .iter()
+            .any(|x| x.eq_ignore_ascii_case(username));
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -29,6 +220,125 @@ pub struct Game {
     pub current_round: usize,
     pub state: GameState,
     pub log: Vec<GameLog>,
+    #[serde(default)]
+    pub final_round: Option<FinalRound>,
+    #[serde(default)]
+    pub settings: GameSettings,
+    #[serde(default)]
+    pub timer: Option<GameTimer>,
+    #[serde(default)]
+    pub teams: Vec<Team>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase", default)]
+pub struct GameSettings {
+    /// How long after the first buzz other buzzes are still accepted, so the fastest player wins
+    /// rather than whoever has the fastest connection
+    pub buzz_window_ms: u64,
+    /// Adjust each buzz by half of the player's measured round trip time
+    pub latency_compensation: bool,
+    /// Time players have to buzz in once answering is allowed, before the question is passed
+    pub buzz_time_limit_secs: Option<u64>,
+    /// Time a player has to answer after buzzing in, before their answer is marked incorrect
+    pub answer_time_limit_secs: Option<u64>,
+    pub answer_mode: AnswerMode,
+}
+
+#[derive(Clone, Serialize, Deserialize, PartialEq, Debug, Default)]
+pub enum AnswerMode {
+    /// Players say their answer out loud, and the host decides if it is correct
+    #[default]
+    Spoken,
+    /// Players type their answer, and the server suggests if it is correct for the host to confirm
+    Suggest,
+    /// Players type their answer, and the server decides if it is correct
+    AutoGrade,
+}
+
+impl GameSettings {
+    /// Whether the time limits are all within a sensible range. Time limits can't be 0, as that
+    /// would end every question before anyone could buzz in.
+    pub fn has_valid_limits(&self) -> bool {
+        let is_valid = |x: Option<u64>| x.is_none_or(|x| (1..=MAX_TIME_LIMIT_SECS).contains(&x));
+        return self.buzz_window_ms <= MAX_BUZZ_WINDOW_MS
+            && is_valid(self.buzz_time_limit_secs)
+            && is_valid(self.answer_time_limit_secs);
+    }
+}
+
+impl Default for GameSettings {
+    fn default() -> Self {
+        GameSettings {
+            buzz_window_ms: 150,
+            latency_compensation: false,
+            buzz_time_limit_secs: None,
+            answer_time_limit_secs: None,
+            answer_mode: AnswerMode::Spoken,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct GameTimer {
+    pub kind: TimerKind,
+    /// Time the timer fires, in milliseconds since the epoch
+    pub deadline: u64,
+}
+
+#[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PausedTimer {
+    pub kind: TimerKind,
+    pub remaining_ms: u64,
+}
+
+#[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
+pub enum TimerKind {
+    BuzzWindow,
+    BuzzTimeout,
+    AnswerTimeout,
+}
+
+#[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Buzz {
+    pub username: String,
+    pub received_at: u64,
+    /// Milliseconds subtracted from `received_at` to make up for the player's latency
+    pub latency_compensation: u64,
+}
+
+impl Game {
+    /// Copy of the game that is safe to show to contestants and spectators, without the answers to
+    /// unanswered questions or the final round wagers and answers that haven't been revealed.
+    pub fn redacted(&self) -> Game {
+        let mut game = self.clone();
+        for question in game
+            .rounds
+            .iter_mut()
+            .flatten()
+            .flat_map(|x| x.questions.iter_mut())
+        {
+            *question = question.redacted();
+        }
+        game.state = game.state.redacted();
+        if let Some(final_round) = &mut game.final_round {
+            // The final question is kept secret until every wager is in
+            final_round.question = match game.state.unpaused() {
+                GameState::FinalRoundReveal { .. } | GameState::Finished => {
+                    final_round.question.clone()
+                }
+                GameState::FinalRoundAnswer { .. } => final_round.question.redacted(),
+                _ => final_round.question.hidden(),
+            };
+            let revealed = final_round.revealed.clone();
+            final_round.wagers.retain(|k, _| revealed.contains(k));
+            final_round.answers.retain(|k, _| revealed.contains(k));
+        }
+        return game;
+    }
 }
 
 impl IntoResponse for Game {
@@ -43,6 +353,17 @@ pub struct Player {
     pub username: String,
     pub score: isize,
     pub role: PlayerRole,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub team_id: Option<String>,
+}
+
+/// A group of contestants who buzz in and score together.
+#[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Team {
+    pub team_id: String,
+    pub name: String,
+    pub score: isize,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -61,6 +382,79 @@ pub struct Question {
     pub correct_answer: String,
     pub value: isize,
     pub answered: bool,
+    /// Only the player who picked a daily double may answer it, for a wager of their choosing
+    #[serde(default)]
+    pub daily_double: bool,
+    /// Other answers accepted when answers are typed
+    #[serde(default)]
+    pub alternate_answers: Vec<String>,
+    #[serde(default)]
+    pub kind: QuestionKind,
+    /// Options a contestant picks from, one of which must be the correct answer
+    #[serde(default)]
+    pub choices: Vec<String>,
+    /// Labels for finding questions in the question bank, such as a topic or difficulty
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl Question {
+    /// Whether the question has everything needed to play it.
+    pub fn is_complete(&self) -> bool {
+        return !self.detail.is_empty() && !self.correct_answer.is_empty();
+    }
+
+    /// Copy of the question without its answer, unless it has already been answered.
+    pub fn redacted(&self) -> Question {
+        if self.answered {
+            return self.clone();
+        }
+        return Question {
+            correct_answer: String::new(),
+            alternate_answers: Vec::new(),
+            ..self.clone()
+        };
+    }
+
+    /// Copy of the question without anything that would give it away, for a question that hasn't
+    /// been shown yet.
+    pub fn hidden(&self) -> Question {
+        return Question {
+            detail: String::new(),
+            choices: Vec::new(),
+            ..self.redacted()
+        };
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, PartialEq, Debug, Default)]
+pub enum QuestionKind {
+    #[default]
+    FreeResponse,
+    MultipleChoice,
+    TrueFalse,
+}
+
+#[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SubmittedAnswer {
+    pub answer: String,
+    pub suggested_correct: bool,
+}
+
+/// A single question played after the board is cleared, where each contestant secretly wagers
+/// part of their score before seeing it.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct FinalRound {
+    pub name: String,
+    pub question: Question,
+    #[serde(default)]
+    pub wagers: HashMap<String, isize>,
+    #[serde(default)]
+    pub answers: HashMap<String, String>,
+    #[serde(default)]
+    pub revealed: Vec<String>,
 }
 
 #[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
@@ -70,6 +464,17 @@ pub enum PlayerRole {
     Spectator,
 }
 
+impl PlayerRole {
+    /// Name of the role, to label metrics with.
+    pub fn kind(&self) -> &'static str {
+        return match self {
+            PlayerRole::Host => "Host",
+            PlayerRole::Contestant => "Contestant",
+            PlayerRole::Spectator => "Spectator",
+        };
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
 #[serde(tag = "state")]
 pub enum GameState {
@@ -87,46 +492,381 @@ pub enum GameState {
     CheckAnswer {
         question: Question,
         player: Player,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        wager: Option<isize>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        submitted_answer: Option<SubmittedAnswer>,
+    },
+    #[serde(rename_all = "camelCase")]
+    DailyDoubleWager {
+        question: Question,
+        player: Player,
+    },
+    #[serde(rename_all = "camelCase")]
+    FinalRoundWager {
+        category: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    FinalRoundAnswer {
+        category: String,
+        question: Question,
+    },
+    #[serde(rename_all = "camelCase")]
+    FinalRoundReveal {
+        question: Question,
+        username: String,
+        answer: String,
+        wager: isize,
+    },
+    #[serde(rename_all = "camelCase")]
+    Paused {
+        previous: Box<GameState>,
+        /// The countdown that was running when the game was paused, to carry on once resumed
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timer: Option<PausedTimer>,
     },
     Finished,
 }
 
+impl GameState {
+    /// Name of the state, without the question or players, to label metrics with.
+    pub fn kind(&self) -> &'static str {
+        return match self {
+            GameState::WaitingToStart => "WaitingToStart",
+            GameState::PickAQuestion => "PickAQuestion",
+            GameState::ReadQuestion { .. } => "ReadQuestion",
+            GameState::WaitingForAnswer { .. } => "WaitingForAnswer",
+            GameState::CheckAnswer { .. } => "CheckAnswer",
+            GameState::DailyDoubleWager { .. } => "DailyDoubleWager",
+            GameState::FinalRoundWager { .. } => "FinalRoundWager",
+            GameState::FinalRoundAnswer { .. } => "FinalRoundAnswer",
+            GameState::FinalRoundReveal { .. } => "FinalRoundReveal",
+            GameState::Paused { .. } => "Paused",
+            GameState::Finished => "Finished",
+        };
+    }
+
+    /// Copy of the state with the answer to the current question hidden, while it can still be
+    /// answered. Once the final round answers are being revealed there is nothing left to hide.
+    /// The state the game is in, or was in before it was paused.
+    pub fn unpaused(&self) -> &GameState {
+        return match self {
+            GameState::Paused { previous, .. } => previous.as_ref(),
+            x => x,
+        };
+    }
+
+    pub fn redacted(&self) -> GameState {
+        let mut state = self.clone();
+        match &mut state {
+            GameState::ReadQuestion { question }
+            | GameState::WaitingForAnswer { question }
+            | GameState::CheckAnswer { question, .. }
+            | GameState::FinalRoundAnswer { question, .. } => *question = question.redacted(),
+            // The question is only shown once the player has made their wager
+            GameState::DailyDoubleWager { question, .. } => *question = question.hidden(),
+            GameState::Paused { previous, .. } => **previous = previous.redacted(),
+            _ => {}
+        }
+        return state;
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
 #[serde(tag = "type")]
 pub enum GameLog {
     GameCreated {
-        time: u128,
+        time: u64,
     },
     GameStarted {
-        time: u128,
+        time: u64,
     },
     #[serde(rename_all = "camelCase")]
     QuestionPicked {
-        time: u128,
+        time: u64,
         question_id: String,
     },
     PlayerBuzzedIn {
-        time: u128,
+        time: u64,
         username: String,
+        /// Every buzz received in the buzz window, fastest first
+        #[serde(default)]
+        buzzes: Vec<Buzz>,
     },
     #[serde(rename_all = "camelCase")]
     AnswerConfirmed {
-        time: u128,
+        time: u64,
         username: String,
         is_correct: bool,
         points_change: isize,
         question_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        wager: Option<isize>,
     },
     #[serde(rename_all = "camelCase")]
     QuestionPassed {
-        time: u128,
+        time: u64,
         question_id: String,
     },
+    WagerSubmitted {
+        time: u64,
+        username: String,
+    },
+    FinalAnswerSubmitted {
+        time: u64,
+        username: String,
+    },
+    FinalAnswerRevealed {
+        time: u64,
+        username: String,
+        answer: String,
+        wager: isize,
+    },
+    #[serde(rename_all = "camelCase")]
+    AnswerSubmitted {
+        time: u64,
+        username: String,
+        answer: String,
+        suggested_correct: bool,
+    },
+    #[serde(rename_all = "camelCase")]
+    TeamsUpdated {
+        time: u64,
+    },
     #[serde(rename_all = "camelCase")]
     ManualScoreUpdated {
-        time: u128,
+        time: u64,
         username: String,
         old_score: isize,
         new_score: isize,
     },
+    ActionUndone {
+        time: u64,
+        username: String,
+    },
+    PlayerKicked {
+        time: u64,
+        username: String,
+    },
+    PlayerBanned {
+        time: u64,
+        username: String,
+    },
+    RoleChanged {
+        time: u64,
+        username: String,
+        role: PlayerRole,
+    },
+    GamePaused {
+        time: u64,
+    },
+    GameResumed {
+        time: u64,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dto::CreateGameRequest;
+
+    fn question(question_id: &str, answered: bool) -> Question {
+        return Question {
+            question_id: question_id.to_string(),
+            detail: format!("Question {question_id}"),
+            correct_answer: "The Answer".to_string(),
+            value: 100,
+            answered,
+            daily_double: false,
+            alternate_answers: vec!["An Answer".to_string()],
+            kind: QuestionKind::FreeResponse,
+            choices: Vec::new(),
+            tags: Vec::new(),
+        };
+    }
+
+    /// A game in the final round, where alice's answer has been revealed but bob's hasn't.
+    fn final_round_game() -> Game {
+        let mut game: Game = CreateGameRequest {
+            rounds: vec![vec![Category {
+                category_id: "c1".to_string(),
+                name: "Category".to_string(),
+                questions: vec![question("q1", true), question("q2", false)],
+            }]],
+            final_round: Some(FinalRound {
+                name: "Final".to_string(),
+                question: question("final", false),
+                wagers: HashMap::from([("alice".to_string(), 100), ("bob".to_string(), 200)]),
+                answers: HashMap::from([
+                    ("alice".to_string(), "Alice's Answer".to_string()),
+                    ("bob".to_string(), "Bob's Answer".to_string()),
+                ]),
+                revealed: vec!["alice".to_string()],
+            }),
+            settings: None,
+            board_id: None,
+        }
+        .into();
+        game.state = GameState::FinalRoundAnswer {
+            category: "Final".to_string(),
+            question: question("final", false),
+        };
+        return game;
+    }
+
+    #[test]
+    fn redacted_games_hide_unanswered_questions() {
+        let game = final_round_game().redacted();
+
+        let questions = &game.rounds[0][0].questions;
+        assert_eq!(questions[0].correct_answer, "The Answer");
+        assert_eq!(questions[1].correct_answer, "");
+        assert!(questions[1].alternate_answers.is_empty());
+        assert_eq!(questions[1].detail, "Question q2");
+        match &game.state {
+            GameState::FinalRoundAnswer { question, .. } => assert_eq!(question.correct_answer, ""),
+            _ => panic!("expected the state to be kept"),
+        }
+    }
+
+    #[test]
+    fn redacted_games_hide_unrevealed_final_answers() {
+        let game = final_round_game().redacted();
+
+        let final_round = game.final_round.unwrap();
+        assert_eq!(final_round.question.correct_answer, "");
+        assert_eq!(final_round.wagers.keys().collect::<Vec<_>>(), ["alice"]);
+        assert_eq!(final_round.answers.keys().collect::<Vec<_>>(), ["alice"]);
+    }
+
+    #[test]
+    fn final_answer_is_shown_once_revealing() {
+        let mut game = final_round_game();
+        game.state = GameState::Finished;
+
+        let final_round = game.redacted().final_round.unwrap();
+        assert_eq!(final_round.question.correct_answer, "The Answer");
+    }
+
+    #[test]
+    fn paused_states_are_redacted() {
+        let state = GameState::Paused {
+            previous: Box::new(GameState::ReadQuestion {
+                question: question("q2", false),
+            }),
+            timer: None,
+        };
+        match state.redacted() {
+            GameState::Paused { previous, .. } => {
+                assert_eq!(
+                    *previous,
+                    GameState::ReadQuestion {
+                        question: question("q2", false).redacted(),
+                    }
+                );
+            }
+            _ => panic!("expected the game to stay paused"),
+        }
+    }
+
+    fn game_entry() -> GameEntry {
+        let game: Game = CreateGameRequest {
+            rounds: Vec::new(),
+            final_round: None,
+            settings: None,
+            board_id: None,
+        }
+        .into();
+        return GameEntry::new(game, 16);
+    }
+
+    fn broadcast(game_entry: &mut GameEntry, count: usize) {
+        for index in 0..count {
+            game_entry.broadcast(GameMessage::Ack {
+                request_id: index.to_string(),
+            });
+        }
+    }
+
+    #[test]
+    fn missed_messages_are_replayed_in_order() {
+        let mut game_entry = game_entry();
+        broadcast(&mut game_entry, 5);
+
+        let seqs = |since| {
+            let messages = game_entry.messages_since(since).unwrap();
+            return messages.iter().map(|x| x.seq).collect::<Vec<_>>();
+        };
+        assert_eq!(seqs(2), [3, 4, 5]);
+        assert!(seqs(5).is_empty());
+        assert_eq!(seqs(0), [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn forgotten_or_unknown_messages_need_a_resync() {
+        let mut game_entry = game_entry();
+        broadcast(&mut game_entry, HISTORY_SIZE + 10);
+
+        assert!(game_entry.messages_since(5).is_none());
+        assert!(game_entry
+            .messages_since(HISTORY_SIZE as u64 + 11)
+            .is_none());
+        assert_eq!(game_entry.messages_since(10).unwrap().len(), HISTORY_SIZE);
+    }
+
+    #[test]
+    fn final_question_is_hidden_until_wagers_are_in() {
+        let mut game = final_round_game();
+        game.state = GameState::FinalRoundWager {
+            category: "Final".to_string(),
+        };
+        let final_round = game.redacted().final_round.unwrap();
+        assert_eq!(final_round.question.detail, "");
+        assert_eq!(final_round.question.correct_answer, "");
+
+        game.state = GameState::Paused {
+            previous: Box::new(game.state.clone()),
+            timer: None,
+        };
+        let final_round = game.redacted().final_round.unwrap();
+        assert_eq!(final_round.question.detail, "");
+
+        let final_round = final_round_game().redacted().final_round.unwrap();
+        assert_eq!(final_round.question.detail, "Question final");
+    }
+
+    #[test]
+    fn daily_doubles_are_hidden_until_the_wager_is_in() {
+        let player = Player {
+            username: "alice".to_string(),
+            score: 0,
+            role: PlayerRole::Contestant,
+            team_id: None,
+        };
+        let wagering = GameState::DailyDoubleWager {
+            question: question("q2", false),
+            player: player.clone(),
+        };
+        match wagering.redacted() {
+            GameState::DailyDoubleWager { question, .. } => {
+                assert_eq!(question.detail, "");
+                assert_eq!(question.value, 100);
+            }
+            _ => panic!("expected the state to be kept"),
+        }
+
+        let answering = GameState::CheckAnswer {
+            question: question("q2", false),
+            player,
+            wager: Some(100),
+            submitted_answer: None,
+        };
+        match answering.redacted() {
+            GameState::CheckAnswer { question, .. } => {
+                assert_eq!(question.detail, "Question q2");
+                assert_eq!(question.correct_answer, "");
+            }
+            _ => panic!("expected the state to be kept"),
+        }
+    }
 }
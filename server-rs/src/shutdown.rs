@@ -0,0 +1,118 @@
+use std::time::Duration;
+
+use tokio::signal;
+
+use crate::{dto::GameMessage, models::AppState};
+
+impl AppState {
+    pub fn is_shutting_down(&self) -> bool {
+        return *self.shutdown.borrow();
+    }
+
+    /// Stop accepting new games and players, let everyone know the server is going away and save
+    /// every game so it can be restored when the server starts again.
+    pub fn begin_shutdown(&self) {
+        self.shutdown.send_replace(true);
+
+        for mut game_entry in self.games.iter_mut() {
+            game_entry.broadcast(GameMessage::ServerShuttingDown {
+                reconnect_after_secs: self.config.reconnect_after_secs,
+            });
+            self.save_game(&game_entry);
+        }
+    }
+
+    /// Completes once the shutdown timeout has passed since the server started shutting down.
+    pub async fn shutdown_timeout(&self) {
+        let _ = self.shutdown.subscribe().wait_for(|x| *x).await;
+        tokio::time::sleep(Duration::from_secs(self.config.shutdown_timeout_secs)).await;
+    }
+}
+
+/// Completes once the server has been asked to stop with Ctrl+C or SIGTERM, after starting the
+/// shutdown.
+pub async fn shutdown_signal(state: AppState) {
+    let ctrl_c = async {
+        signal::ctrl_c()
+            .await
+            .expect("failed to install the Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("failed to install the SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    tracing::info!("Shutting down");
+    state.begin_shutdown();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        actions::GameError,
+        boards::MemoryBoardStore,
+        config::ServerConfig,
+        dto::CreateGameRequest,
+        models::{Game, GameEntry, PlayerRole},
+        store::MemoryGameStore,
+    };
+
+    fn request() -> CreateGameRequest {
+        return CreateGameRequest {
+            rounds: Vec::new(),
+            final_round: None,
+            settings: None,
+            board_id: None,
+        };
+    }
+
+    #[test]
+    fn shutting_down_tells_players_and_turns_new_ones_away() {
+        let config = ServerConfig {
+            reconnect_after_secs: Some(30),
+            ..ServerConfig::default()
+        };
+        let state = AppState::new(
+            config,
+            Arc::new(MemoryGameStore),
+            Arc::new(MemoryBoardStore),
+        );
+        let game: Game = request().into();
+        let game_id = game.id.clone();
+        let game_entry = GameEntry::new(game, 16);
+        let mut receiver = game_entry.sender.subscribe();
+        state.games.insert(game_id.clone(), game_entry);
+
+        state.begin_shutdown();
+        assert!(state.is_shutting_down());
+        assert!(matches!(
+            receiver.try_recv().unwrap().message,
+            GameMessage::ServerShuttingDown {
+                reconnect_after_secs: Some(30)
+            }
+        ));
+
+        let result = state.clone().create_game(request());
+        assert!(matches!(result, Err(GameError::ServerShuttingDown)));
+        let username = "alice".to_string();
+        let result = state
+            .clone()
+            .join_game(game_id, username, PlayerRole::Spectator, None, None);
+        assert!(matches!(result, Err(GameError::ServerShuttingDown)));
+    }
+}
@@ -0,0 +1,281 @@
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::{AppState, Game, GameAuth, GameEntry};
+
+/// Storage backend used to persist games so they survive a server restart.
+pub trait GameStore: Send + Sync {
+    fn save(&self, snapshot: &GameSnapshot) -> Result<(), StoreError>;
+    fn remove(&self, game_id: &str) -> Result<(), StoreError>;
+    fn load_all(&self) -> Result<Vec<GameSnapshot>, StoreError>;
+    /// Wait for every save and removal so far to be written.
+    fn flush(&self);
+}
+
+/// Everything needed to bring a game back after a restart.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct GameSnapshot {
+    pub game: Game,
+    pub auth: GameAuth,
+    /// Keeps message numbers increasing after a restart, so clients don't mistake new messages for old
+    #[serde(default)]
+    pub seq: u64,
+}
+
+impl Into<GameSnapshot> for &GameEntry {
+    fn into(self) -> GameSnapshot {
+        GameSnapshot {
+            game: self.game.clone(),
+            auth: self.auth.clone(),
+            seq: self.seq,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum StoreError {
+    Io(io::Error),
+    Serialization(serde_json::Error),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Io(e) => write!(f, "io error: {e}"),
+            StoreError::Serialization(e) => write!(f, "serialization error: {e}"),
+        }
+    }
+}
+
+impl From<io::Error> for StoreError {
+    fn from(value: io::Error) -> Self {
+        StoreError::Io(value)
+    }
+}
+
+impl From<serde_json::Error> for StoreError {
+    fn from(value: serde_json::Error) -> Self {
+        StoreError::Serialization(value)
+    }
+}
+
+/// Keeps games only in `AppState.games`, so nothing survives a restart.
+pub struct MemoryGameStore;
+
+impl GameStore for MemoryGameStore {
+    fn save(&self, _snapshot: &GameSnapshot) -> Result<(), StoreError> {
+        return Ok(());
+    }
+
+    fn remove(&self, _game_id: &str) -> Result<(), StoreError> {
+        return Ok(());
+    }
+
+    fn load_all(&self) -> Result<Vec<GameSnapshot>, StoreError> {
+        return Ok(Vec::new());
+    }
+
+    fn flush(&self) {}
+}
+
+enum WriteCommand {
+    Write { path: PathBuf, contents: Vec<u8> },
+    Remove { path: PathBuf },
+    Flush { done: mpsc::Sender<()> },
+}
+
+/// Writes files on a background thread in the order they were queued, so saving never holds up a
+/// game while it is locked.
+pub struct FileWriter {
+    sender: mpsc::Sender<WriteCommand>,
+}
+
+impl FileWriter {
+    pub fn new() -> FileWriter {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for command in receiver {
+                match command {
+                    WriteCommand::Write { path, contents } => {
+                        // Write to a temporary file first so a crash mid-write never leaves a
+                        // truncated file behind
+                        let tmp_path = path.with_extension("json.tmp");
+                        let result = fs::write(&tmp_path, contents)
+                            .and_then(|_| fs::rename(tmp_path, &path));
+                        if let Err(e) = result {
+                            tracing::error!("Failed to write {path:?}: {e}");
+                        }
+                    }
+                    WriteCommand::Remove { path } => match fs::remove_file(&path) {
+                        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                            tracing::error!("Failed to remove {path:?}: {e}");
+                        }
+                        _ => {}
+                    },
+                    WriteCommand::Flush { done } => {
+                        let _ = done.send(());
+                    }
+                }
+            }
+        });
+        return FileWriter { sender };
+    }
+
+    pub fn write(&self, path: PathBuf, contents: Vec<u8>) {
+        let _ = self.sender.send(WriteCommand::Write { path, contents });
+    }
+
+    pub fn remove(&self, path: PathBuf) {
+        let _ = self.sender.send(WriteCommand::Remove { path });
+    }
+
+    /// Block until everything queued so far has been written.
+    pub fn flush(&self) {
+        let (done, wait) = mpsc::channel();
+        if self.sender.send(WriteCommand::Flush { done }).is_ok() {
+            let _ = wait.recv();
+        }
+    }
+}
+
+/// Stores each game as a JSON file named after the game ID inside a directory.
+pub struct FileGameStore {
+    dir: PathBuf,
+    writer: FileWriter,
+}
+
+impl FileGameStore {
+    pub fn new(dir: impl AsRef<Path>) -> Result<FileGameStore, StoreError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        return Ok(FileGameStore {
+            dir,
+            writer: FileWriter::new(),
+        });
+    }
+
+    fn path_for(&self, game_id: &str) -> PathBuf {
+        return self.dir.join(format!("{game_id}.json"));
+    }
+}
+
+impl GameStore for FileGameStore {
+    fn save(&self, snapshot: &GameSnapshot) -> Result<(), StoreError> {
+        self.writer.write(
+            self.path_for(&snapshot.game.id),
+            serde_json::to_vec(snapshot)?,
+        );
+        return Ok(());
+    }
+
+    fn remove(&self, game_id: &str) -> Result<(), StoreError> {
+        self.writer.remove(self.path_for(game_id));
+        return Ok(());
+    }
+
+    fn load_all(&self) -> Result<Vec<GameSnapshot>, StoreError> {
+        let mut games = Vec::new();
+        for file in fs::read_dir(&self.dir)? {
+            let path = file?.path();
+            if path.extension().is_none_or(|x| x != "json") {
+                continue;
+            }
+
+            let parsed = fs::read(&path)
+                .map_err(StoreError::from)
+                .and_then(|x| serde_json::from_slice::<GameSnapshot>(&x).map_err(StoreError::from));
+            match parsed {
+                Ok(game) => games.push(game),
+                Err(e) => tracing::warn!("Skipping unreadable game file {path:?}: {e}"),
+            }
+        }
+        return Ok(games);
+    }
+
+    fn flush(&self) {
+        self.writer.flush();
+    }
+}
+
+impl AppState {
+    /// Persist the latest state of a game. The game is copied while it is locked, and written in the
+    /// background. Failures are logged rather than surfaced to players, as the game can carry on in
+    /// memory.
+    pub fn save_game(&self, game_entry: &GameEntry) {
+        if let Err(e) = self.store.save(&game_entry.into()) {
+            tracing::error!("Failed to persist game {}: {e}", game_entry.game.id);
+        }
+    }
+
+    pub fn remove_saved_game(&self, game_id: &str) {
+        if let Err(e) = self.store.remove(game_id) {
+            tracing::error!("Failed to remove persisted game {game_id}: {e}");
+        }
+    }
+
+    /// Load all persisted games back into memory, recreating their channels so players can reconnect.
+    pub fn restore_games(&self) {
+        let games = match self.store.load_all() {
+            Ok(x) => x,
+            Err(e) => {
+                tracing::error!("Failed to load persisted games: {e}");
+                return;
+            }
+        };
+
+        for snapshot in games {
+            tracing::info!("Restoring game {}", snapshot.game.id);
+            let mut game_entry = GameEntry::new(snapshot.game, self.config.channel_capacity);
+            game_entry.auth = snapshot.auth;
+            game_entry.seq = snapshot.seq;
+            self.insert_game(game_entry);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dto::CreateGameRequest;
+
+    fn snapshot(game_id: &str, seq: u64) -> GameSnapshot {
+        let mut game: Game = CreateGameRequest {
+            rounds: Vec::new(),
+            final_round: None,
+            settings: None,
+            board_id: None,
+        }
+        .into();
+        game.id = game_id.to_string();
+        return GameSnapshot {
+            game,
+            auth: GameAuth::default(),
+            seq,
+        };
+    }
+
+    #[test]
+    fn file_store_writes_saves_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FileGameStore::new(dir.path()).unwrap();
+
+        store.save(&snapshot("ABCD", 1)).unwrap();
+        store.save(&snapshot("ABCD", 2)).unwrap();
+        store.save(&snapshot("EFGH", 1)).unwrap();
+        store.remove("EFGH").unwrap();
+        store.flush();
+
+        let games = store.load_all().unwrap();
+        assert_eq!(games.len(), 1);
+        assert_eq!(games[0].game.id, "ABCD");
+        assert_eq!(games[0].seq, 2);
+        assert!(!dir.path().join("ABCD.json.tmp").exists());
+    }
+}
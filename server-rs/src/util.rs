@@ -1,8 +1,28 @@
 use std::time::{Duration, SystemTime};
 
-pub fn get_time() -> u128 {
+use axum::http::{header, HeaderMap};
+use rand::{distributions::Alphanumeric, Rng};
+
+pub fn get_time() -> u64 {
     SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
         .unwrap_or(Duration::from_secs(0))
-        .as_millis()
+        .as_millis() as u64
+}
+
+/// Generate a random secret suitable for authenticating a player.
+pub fn generate_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
+}
+
+/// Token from an `Authorization: Bearer <token>` header, if there is one.
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    return headers
+        .get(header::AUTHORIZATION)
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.strip_prefix("Bearer "));
 }
@@ -7,26 +7,35 @@ use axum::{
     body::Body,
     extract::{
         ws::{CloseFrame, Message, WebSocket},
-        Path, State, WebSocketUpgrade,
+        Path, Query, State, WebSocketUpgrade,
     },
     response::Response,
 };
 use futures::{SinkExt, StreamExt};
-use tokio::{sync::broadcast::error::TryRecvError, time::Instant};
+use tokio::sync::{broadcast::error::RecvError, mpsc};
 
 use crate::{
-    actions,
-    dto::{GameMessage, UpdateGameRequest},
+    dto::{
+        ClientRequest, GameMessage, JoinGameQuery, SequencedMessage,
+        SERVER_SHUTTING_DOWN_CLOSE_CODE,
+    },
+    metrics::TrackedConnection,
     models::{AppState, PlayerRole},
+    util::get_time,
 };
 
+/// Number of replies that can be waiting to be sent to a client before it stops reading requests
+const REPLY_CAPACITY: usize = 16;
+
 pub async fn join_game(
     Path((game_id, role, username)): Path<(String, PlayerRole, String)>,
+    Query(query): Query<JoinGameQuery>,
     ws: WebSocketUpgrade,
     State(state): State<AppState>,
 ) -> Response<Body> {
     tracing::debug!("{username} is attempting to join game {game_id} as a {role:?}");
-    return ws.on_upgrade(move |socket| handle_socket(socket, game_id, username, role, state));
+    return ws
+        .on_upgrade(move |socket| handle_socket(socket, game_id, username, role, query, state));
 }
 
 async fn handle_socket(
@@ -34,16 +43,23 @@ async fn handle_socket(
     game_id: String,
     username: String,
     role: PlayerRole,
+    query: JoinGameQuery,
     state: AppState,
 ) {
     let (mut ws_tx, mut ws_rx) = socket.split();
+    // Lets shutdown wait for this connection to close
+    let _shutdown = state.shutdown.subscribe();
 
-    match state
-        .clone()
-        .join_game(game_id.clone(), username.clone(), role.clone())
-    {
-        Ok(_) => {}
+    let token = match state.clone().join_game(
+        game_id.clone(),
+        username.clone(),
+        role.clone(),
+        query.token,
+        query.team,
+    ) {
+        Ok(x) => x,
         Err(e) => {
+            state.metrics.record_error(&e);
             let _ = ws_tx
                 .send(Message::Close(Some(CloseFrame {
                     code: 3002,
@@ -52,23 +68,84 @@ async fn handle_socket(
                 .await;
             return;
         }
-    }
+    };
     let games = state.clone().games;
 
     let ws_tx_games = games.clone();
     let ws_tx_game_id = game_id.clone();
     let ws_tx_username = username.clone();
+    let ws_tx_token = token.clone();
+    // Replies meant only for this connection, such as errors. Dropped by the receiving task when the
+    // client closes the connection.
+    let (reply_tx, mut reply_rx) = mpsc::channel::<GameMessage>(REPLY_CAPACITY);
+    let ws_tx_since = query.since;
+    let ws_tx_metrics = state.metrics.clone();
     let mut ws_tx_task = tokio::spawn(async move {
         let game_entry = match ws_tx_games.get_mut(&ws_tx_game_id.to_ascii_uppercase()) {
             Some(x) => x,
             None => return,
         };
 
-        // Send the current game start to initialise the client
-        if let Ok(serialized) = serde_json::to_string(&GameMessage::JoinGame {
-            game: game_entry.game.borrow().into(),
-        }) {
-            if let Err(e) = ws_tx.send(Message::Text(serialized)).await {
+        // Use the role the player actually has in the game to decide what they are allowed to see
+        let mut ws_tx_role = match game_entry
+            .game
+            .players
+            .iter()
+            .find(|x| x.username == ws_tx_username)
+        {
+            Some(x) => x.role.clone(),
+            None => return,
+        };
+        // Counted until this task ends, including when it is aborted
+        let mut tracked_connection = TrackedConnection::new(&ws_tx_metrics, ws_tx_role.clone());
+
+        // A reconnecting client only needs the messages it missed. If they can't all be replayed,
+        // send the full state of the game instead.
+        let initial_messages: Vec<SequencedMessage> =
+            match ws_tx_since.map(|x| game_entry.messages_since(x)) {
+                Some(Some(missed)) => missed
+                    .into_iter()
+                    .filter(|x| !matches!(x.message, GameMessage::EndSession { .. }))
+                    .collect(),
+                Some(None) => vec![SequencedMessage {
+                    seq: game_entry.seq,
+                    message: GameMessage::Resync {
+                        game: Box::new(game_entry.game.clone()),
+                    },
+                }],
+                None => vec![SequencedMessage {
+                    seq: game_entry.seq,
+                    message: GameMessage::JoinGame {
+                        game: game_entry.game.borrow().into(),
+                    },
+                }],
+            };
+
+        let mut game_events_rx = game_entry.receiver.resubscribe();
+
+        // Ensure we release the lock on the game now that we have the channel set up
+        drop(game_entry);
+
+        // Let the client know the token it should use to reconnect, before catching it up. Like
+        // other replies, it isn't one of the game's messages so has no `seq`.
+        let authenticated = GameMessage::Authenticated { token: ws_tx_token };
+        if let Ok(text) = serde_json::to_string(&authenticated) {
+            if let Err(e) = ws_tx.send(Message::Text(text)).await {
+                tracing::warn!(
+                    "Error sending the token for {ws_tx_game_id} to {ws_tx_username}: {e}"
+                );
+                return;
+            }
+        }
+
+        for update in initial_messages {
+            let message = to_client_message(update, &ws_tx_username, &mut ws_tx_role);
+            tracked_connection.set_role(&ws_tx_role);
+            let message = match message {
+                Some(x) => x,
+                None => continue,
+            };
+            if let Err(e) = ws_tx.send(message).await {
                 tracing::warn!(
                     "Error sending game init payload for {ws_tx_game_id} to {ws_tx_username}: {e}"
                 );
@@ -76,91 +153,101 @@ async fn handle_socket(
             }
         }
 
-        let mut game_events_rx = game_entry.receiver.resubscribe();
+        // Measure latency straight away, rather than waiting for the first heartbeat
+        let _ = ws_tx.send(heartbeat()).await;
 
-        // Ensure we release the lock on the game now that we have the channel set up
-        drop(game_entry);
+        // Send the heartbeat on a schedule. The first tick completes straight away, and the
+        // heartbeat has just been sent, so skip it.
+        let mut heartbeat_interval = tokio::time::interval(Duration::from_secs(20));
+        heartbeat_interval.tick().await;
 
-        // Listen for updates on the games channel and send them to the client
-        let mut last_heartbeat = Instant::now();
+        // Wait for updates on the games channel and send them to the client, only waking up when
+        // there is something to do
         loop {
-            tokio::time::sleep(Duration::from_millis(10)).await;
-
-            // Uses try_recv to not block, and allow sending a heartbeat/ping on a schedule.
-            let update = match game_events_rx.try_recv() {
-                Ok(x) => x,
-                Err(err) => {
-                    match err {
-                        TryRecvError::Lagged(_) => {
-                            tracing::warn!(
-                                "Channel for game {ws_tx_game_id} to {ws_tx_username} lagged"
-                            );
-                            continue;
-                        }
-                        TryRecvError::Empty => {
-                            // No messages waiting to be sent. Check if heartbeat needs to be sent.
-                            if Instant::now().duration_since(last_heartbeat)
-                                > Duration::from_secs(20)
-                            {
-                                let _ = ws_tx.send(Message::Ping("SERVER_HEARTBEAT".into())).await;
-                                last_heartbeat = Instant::now();
-                            }
-                            continue;
-                        }
-                        TryRecvError::Closed => {
-                            tracing::debug!(
-                                "Channel for game {ws_tx_game_id} to {ws_tx_username} closed"
-                            );
-                            return;
+            // Game updates go first, so a reply to a request follows the update it caused
+            let update = tokio::select! {
+                biased;
+                update = game_events_rx.recv() => match update {
+                    Ok(x) => x,
+                    Err(RecvError::Lagged(skipped)) => {
+                        tracing::warn!(
+                            "Channel for game {ws_tx_game_id} to {ws_tx_username} lagged by {skipped} messages, resyncing"
+                        );
+                        let game_entry = match ws_tx_games.get(&ws_tx_game_id.to_ascii_uppercase()) {
+                            Some(x) => x,
+                            None => return,
+                        };
+                        ws_tx_metrics.lagged_clients.inc();
+                        // The snapshot already covers the messages still waiting in the channel, so
+                        // skip them. Holding the lock means no new messages can be missed meanwhile.
+                        game_events_rx = game_events_rx.resubscribe();
+                        SequencedMessage {
+                            seq: game_entry.seq,
+                            message: GameMessage::Resync {
+                                game: Box::new(game_entry.game.clone()),
+                            },
                         }
                     }
-                }
-            };
-            match &update {
-                GameMessage::EndSession { username } => {
-                    if username == &ws_tx_username {
-                        let _ = ws_tx
-                            .send(Message::Close(Some(CloseFrame {
-                                code: 3001,
-                                reason: std::borrow::Cow::Borrowed(
-                                    "User requested to leave the game",
-                                ),
-                            })))
-                            .await;
+                    Err(RecvError::Closed) => {
+                        tracing::debug!(
+                            "Channel for game {ws_tx_game_id} to {ws_tx_username} closed"
+                        );
                         return;
                     }
+                },
+                _ = heartbeat_interval.tick() => {
+                    let _ = ws_tx.send(heartbeat()).await;
+                    continue;
                 }
-                GameMessage::ReportError {
-                    error: _,
-                    message: _,
-                    username,
-                } => {
-                    if username == &ws_tx_username {
-                        if let Ok(serialized) = serde_json::to_string(&update) {
-                            if let Err(e) = ws_tx.send(Message::Text(serialized)).await {
-                                tracing::warn!(
-                                    "Error sending game update for {ws_tx_game_id} to {ws_tx_username}: {e}"
-                                );
-                            }
+                reply = reply_rx.recv() => match reply {
+                    Some(x) => {
+                        if let Ok(text) = serde_json::to_string(&x) {
+                            let _ = ws_tx.send(Message::Text(text)).await;
                         }
+                        continue;
                     }
-                }
-                _ => {
-                    if let Ok(serialized) = serde_json::to_string(&update) {
-                        if let Err(e) = ws_tx.send(Message::Text(serialized)).await {
-                            tracing::warn!(
-                                "Error sending game update for {ws_tx_game_id} to {ws_tx_username}: {e}"
-                            );
-                        }
+                    None => {
+                        tracing::debug!("Client {ws_tx_username} closed the connection to {ws_tx_game_id}");
+                        // Flushes the reply to the client's close frame
+                        let _ = ws_tx.close().await;
+                        return;
                     }
-                }
+                },
+            };
+
+            let is_shutting_down = matches!(update.message, GameMessage::ServerShuttingDown { .. });
+            let message = to_client_message(update, &ws_tx_username, &mut ws_tx_role);
+            tracked_connection.set_role(&ws_tx_role);
+            let message = match message {
+                Some(x) => x,
+                None => continue,
+            };
+            let is_close = matches!(message, Message::Close(_));
+            if let Err(e) = ws_tx.send(message).await {
+                tracing::warn!(
+                    "Error sending game update for {ws_tx_game_id} to {ws_tx_username}: {e}"
+                );
+            }
+            if is_close {
+                return;
+            }
+            if is_shutting_down {
+                let _ = ws_tx
+                    .send(Message::Close(Some(CloseFrame {
+                        code: SERVER_SHUTTING_DOWN_CLOSE_CODE,
+                        reason: Cow::Borrowed("The server is shutting down"),
+                    })))
+                    .await;
+                return;
             }
         }
     });
 
     let ws_rx_games = games.clone();
+    let ws_rx_state = state.clone();
     let ws_rx_game_id = game_id.clone();
     let ws_rx_username = username.clone();
+    let ws_rx_token = token.clone();
     let mut ws_rx_task = tokio::spawn(async move {
         while let Some(Ok(msg)) = ws_rx.next().await {
             let request = match msg {
@@ -168,36 +255,362 @@ async fn handle_socket(
                     tracing::debug!("received ping from {ws_rx_username}");
                     continue;
                 }
-                Message::Text(text) => {
-                    match serde_json::from_str::<UpdateGameRequest>(text.as_str()) {
-                        Ok(x) => x,
-                        Err(e) => {
-                            tracing::warn!("Failed to parse the message from {ws_rx_username} for game {ws_rx_game_id}: {text}: {e}");
-                            continue;
+                Message::Pong(payload) => {
+                    if let Ok(sent) = <[u8; 8]>::try_from(payload.as_slice()) {
+                        let latency = get_time().saturating_sub(u64::from_be_bytes(sent));
+                        if let Some(mut game_entry) =
+                            ws_rx_games.get_mut(&ws_rx_game_id.to_ascii_uppercase())
+                        {
+                            game_entry.latencies.insert(ws_rx_username.clone(), latency);
                         }
                     }
+                    continue;
                 }
+                Message::Text(text) => match serde_json::from_str::<ClientRequest>(text.as_str()) {
+                    Ok(x) => x,
+                    Err(e) => {
+                        tracing::warn!("Failed to parse the message from {ws_rx_username} for game {ws_rx_game_id}: {text}: {e}");
+                        continue;
+                    }
+                },
                 Message::Close(close_frame) => {
                     tracing::debug!("close frame received for {ws_rx_username} {close_frame:?}");
-                    continue;
+                    break;
                 }
                 _ => continue,
             };
 
-            let mut game_entry = match ws_rx_games.get_mut(&ws_rx_game_id.to_ascii_uppercase()) {
-                Some(x) => x,
-                None => return,
+            let result = {
+                let mut game_entry = match ws_rx_games.get_mut(&ws_rx_game_id.to_ascii_uppercase())
+                {
+                    Some(x) => x,
+                    None => return,
+                };
+                ws_rx_state.process_game_request(
+                    &mut game_entry,
+                    ws_rx_token.clone(),
+                    request.request,
+                )
             };
 
-            actions::handle_game_request(&mut game_entry, ws_rx_username.clone(), request);
+            // Let the player know why their request failed, or that it succeeded if they want to
+            // know. Everyone else finds out about successful requests from the game update.
+            let reply = match (result, request.request_id) {
+                (Err(error), request_id) => GameMessage::ReportError {
+                    message: error.get_message(),
+                    error,
+                    request_id,
+                },
+                (Ok(_), Some(request_id)) => GameMessage::Ack { request_id },
+                (Ok(_), None) => continue,
+            };
+            if reply_tx.send(reply).await.is_err() {
+                return;
+            }
         }
         return;
     });
 
     tokio::select! {
         _ = (&mut ws_tx_task) => ws_rx_task.abort(),
-        _ = (&mut ws_rx_task) => ws_tx_task.abort(),
+        _ = (&mut ws_rx_task) => {
+            // Give the sending task a moment to reply to the client's close frame
+            let _ = tokio::time::timeout(Duration::from_secs(1), &mut ws_tx_task).await;
+            ws_tx_task.abort();
+        }
     }
 
     tracing::info!("Websocket closed for {username} in game {game_id}");
 }
+
+/// Prepare a message from the game's channel for this client, or `None` if it isn't meant for them.
+fn to_client_message(
+    update: SequencedMessage,
+    username: &str,
+    role: &mut PlayerRole,
+) -> Option<Message> {
+    // Roles can change during the game, so keep track of the player's latest role
+    let players = match &update.message {
+        GameMessage::JoinGame { game } | GameMessage::GameUpdate { game } => Some(&game.players),
+        GameMessage::Resync { game } => Some(&game.players),
+        _ => None,
+    };
+    if let Some(player) = players.and_then(|x| x.iter().find(|x| x.username == username)) {
+        *role = player.role.clone();
+    }
+
+    let message = match update.message {
+        GameMessage::EndSession {
+            username: x,
+            reason,
+        } => {
+            if x != username {
+                return None;
+            }
+            return Some(Message::Close(Some(CloseFrame {
+                code: reason.get_close_code(),
+                reason: Cow::Borrowed(reason.get_message()),
+            })));
+        }
+        x => x.redacted_for(role),
+    };
+
+    let sequenced = SequencedMessage {
+        seq: update.seq,
+        message,
+    };
+    return serde_json::to_string(&sequenced).ok().map(Message::Text);
+}
+
+/// Heartbeat pings carry the time they were sent, so the pong can be used to measure latency.
+fn heartbeat() -> Message {
+    return Message::Ping(get_time().to_be_bytes().to_vec());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::{routing::get, Router};
+    use tokio_tungstenite::{connect_async, tungstenite, MaybeTlsStream, WebSocketStream};
+
+    use super::*;
+    use crate::{
+        boards::MemoryBoardStore,
+        config::ServerConfig,
+        dto::{CreateGameRequest, EndSessionReason},
+        models::{Game, GameEntry, GameState, Player, Question, QuestionKind},
+        store::MemoryGameStore,
+    };
+
+    type Client = WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>;
+
+    fn question() -> Question {
+        return Question {
+            question_id: "q1".to_string(),
+            detail: "Question".to_string(),
+            correct_answer: "The Answer".to_string(),
+            value: 100,
+            answered: false,
+            daily_double: false,
+            alternate_answers: Vec::new(),
+            kind: QuestionKind::FreeResponse,
+            choices: Vec::new(),
+            tags: Vec::new(),
+        };
+    }
+
+    /// An update where alice has the given role, while a question is being read.
+    fn game_update(role: PlayerRole) -> SequencedMessage {
+        let mut game: Game = CreateGameRequest {
+            rounds: Vec::new(),
+            final_round: None,
+            settings: None,
+            board_id: None,
+        }
+        .into();
+        game.players.push(Player {
+            username: "alice".to_string(),
+            score: 0,
+            role,
+            team_id: None,
+        });
+        game.state = GameState::ReadQuestion {
+            question: question(),
+        };
+        return SequencedMessage {
+            seq: 1,
+            message: GameMessage::GameUpdate {
+                game: game.borrow().into(),
+            },
+        };
+    }
+
+    fn text(message: Option<Message>) -> String {
+        return match message {
+            Some(Message::Text(x)) => x,
+            x => panic!("expected a text message, got {x:?}"),
+        };
+    }
+
+    #[test]
+    fn answers_follow_the_players_current_role() {
+        let mut role = PlayerRole::Contestant;
+
+        let message = to_client_message(game_update(PlayerRole::Host), "alice", &mut role);
+        assert_eq!(role, PlayerRole::Host);
+        assert!(text(message).contains("The Answer"));
+
+        let message = to_client_message(game_update(PlayerRole::Spectator), "alice", &mut role);
+        assert_eq!(role, PlayerRole::Spectator);
+        assert!(!text(message).contains("The Answer"));
+    }
+
+    #[test]
+    fn sessions_are_only_ended_for_their_player() {
+        let end_session = SequencedMessage {
+            seq: 1,
+            message: GameMessage::EndSession {
+                username: "bob".to_string(),
+                reason: EndSessionReason::Kicked,
+            },
+        };
+        let mut role = PlayerRole::Contestant;
+
+        assert!(to_client_message(end_session.clone(), "alice", &mut role).is_none());
+        assert!(matches!(
+            to_client_message(end_session, "bob", &mut role),
+            Some(Message::Close(Some(_)))
+        ));
+    }
+
+    /// Serve the WebSocket for a game waiting to start, returning the server's state and address, and
+    /// the game's ID.
+    async fn serve_game() -> (AppState, std::net::SocketAddr, String) {
+        let state = AppState::new(
+            ServerConfig::default(),
+            Arc::new(MemoryGameStore),
+            Arc::new(MemoryBoardStore),
+        );
+        let game: Game = CreateGameRequest {
+            rounds: Vec::new(),
+            final_round: None,
+            settings: None,
+            board_id: None,
+        }
+        .into();
+        let game_id = game.id.clone();
+        state
+            .games
+            .insert(game_id.clone(), GameEntry::new(game, 16));
+
+        let app = Router::new()
+            .route("/api/stream/games/:game_id/:role/:username", get(join_game))
+            .with_state(state.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        return (state, addr, game_id);
+    }
+
+    async fn connect(addr: std::net::SocketAddr, game_id: &str, username: &str) -> Client {
+        let url = format!("ws://{addr}/api/stream/games/{game_id}/Contestant/{username}");
+        let (mut client, _) = connect_async(url).await.unwrap();
+        // Skip the token and the state of the game sent on joining
+        for _ in 0..2 {
+            next_message(&mut client).await.unwrap();
+        }
+        return client;
+    }
+
+    /// The next message from the server other than pings, or `None` if nothing arrives for a while.
+    async fn next_message(client: &mut Client) -> Option<serde_json::Value> {
+        loop {
+            let message = tokio::time::timeout(Duration::from_millis(200), client.next())
+                .await
+                .ok()??
+                .unwrap();
+            if let tungstenite::Message::Text(x) = message {
+                return Some(serde_json::from_str(&x).unwrap());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn errors_are_only_sent_to_the_requesting_connection() {
+        let (_, addr, game_id) = serve_game().await;
+        let mut alice = connect(addr, &game_id, "alice").await;
+        let mut bob = connect(addr, &game_id, "bob").await;
+        // Bob joining is news to alice
+        assert_eq!(
+            next_message(&mut alice).await.unwrap()["type"],
+            "GameUpdate"
+        );
+
+        let request = r#"{"type":"PickQuestion","questionId":"q1","requestId":"r1"}"#;
+        alice
+            .send(tungstenite::Message::Text(request.to_string()))
+            .await
+            .unwrap();
+
+        let reply = next_message(&mut alice).await.unwrap();
+        assert_eq!(reply["type"], "ReportError");
+        assert_eq!(reply["error"], "InsufficientPermissions");
+        assert_eq!(reply["requestId"], "r1");
+        assert!(next_message(&mut bob).await.is_none());
+    }
+
+    /// Connect as a spectator who last saw message `since`, returning the first message after the
+    /// token.
+    async fn reconnect(
+        addr: std::net::SocketAddr,
+        game_id: &str,
+        username: &str,
+        since: u64,
+    ) -> serde_json::Value {
+        let url =
+            format!("ws://{addr}/api/stream/games/{game_id}/Spectator/{username}?since={since}");
+        let (mut client, _) = connect_async(url).await.unwrap();
+        assert_eq!(
+            next_message(&mut client).await.unwrap()["type"],
+            "Authenticated"
+        );
+        return next_message(&mut client).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn reconnecting_replays_missed_messages_or_resyncs() {
+        let (state, addr, game_id) = serve_game().await;
+        // More messages than the game remembers, so the oldest of them can't be replayed
+        let seq = {
+            let mut game_entry = state.games.get_mut(&game_id).unwrap();
+            for index in 0..300 {
+                game_entry.broadcast(GameMessage::Ack {
+                    request_id: index.to_string(),
+                });
+            }
+            game_entry.seq
+        };
+
+        let missed = reconnect(addr, &game_id, "alice", seq - 1).await;
+        assert_eq!(missed["seq"], seq);
+        assert_eq!(missed["requestId"], "299");
+        let resync = reconnect(addr, &game_id, "bob", 1).await;
+        assert_eq!(resync["type"], "Resync");
+    }
+
+    #[tokio::test]
+    async fn clients_that_fall_behind_are_resynced() {
+        let (state, addr, game_id) = serve_game().await;
+        let mut alice = connect(addr, &game_id, "alice").await;
+
+        // The test runs on a single thread, so the connection can't keep up until the burst is over
+        {
+            let mut game_entry = state.games.get_mut(&game_id).unwrap();
+            for index in 0..100 {
+                game_entry.broadcast(GameMessage::Ack {
+                    request_id: index.to_string(),
+                });
+            }
+        }
+
+        let mut types = Vec::new();
+        while let Some(message) = next_message(&mut alice).await {
+            types.push(message["type"].as_str().unwrap().to_string());
+        }
+        assert_eq!(types.first().unwrap(), "Resync");
+        assert!(types.len() < 100);
+    }
+
+    #[tokio::test]
+    async fn tokens_are_sent_without_a_seq() {
+        let (_, addr, game_id) = serve_game().await;
+        let url = format!("ws://{addr}/api/stream/games/{game_id}/Contestant/alice");
+        let (mut client, _) = connect_async(url).await.unwrap();
+
+        let authenticated = next_message(&mut client).await.unwrap();
+        assert_eq!(authenticated["type"], "Authenticated");
+        assert!(authenticated.get("seq").is_none());
+        assert!(next_message(&mut client).await.unwrap()["seq"].is_u64());
+    }
+}
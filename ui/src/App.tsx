@@ -1,5 +1,5 @@
 import GameScreen from "./game/GameScreen";
-import { useEffect, useState } from "react";
+import { useCallback, useMemo, useRef, useState } from "react";
 import { Errors, GameContext } from "./GameContext";
 import { Game, GameMessage, PlayerRole, Preferences } from "./Models";
 import Header from "./layout/Header";
@@ -15,7 +15,8 @@ function App() {
   const [role, setRole] = useSessionStorageState<PlayerRole | undefined>("role", { defaultValue: undefined });
   const [gameId, setGameId] = useSessionStorageState<string>("gameId", { defaultValue: "" });
   const [prefs, setPrefs] = useLocalStorageState<Preferences>("prefs", { defaultValue: { hideGameId: false } });
-  const [wsUrl, setWsUrl] = useState<string | null>(null);
+  // Tokens the server gave us for each game and username, to rejoin as the same player
+  const [tokens, setTokens] = useSessionStorageState<Record<string, string>>("tokens", { defaultValue: {} });
   const [errors, setErrors] = useState<Errors>({});
 
   const removeError = (id: string) => {
@@ -38,13 +39,32 @@ function App() {
     }, 2500);
   };
 
-  useEffect(() => {
-    if (game?.id && role && username) {
-      setWsUrl(`/api/stream/games/${game.id}/${role}/${username}`);
-    } else {
-      setWsUrl(null);
+  const getToken = useCallback(
+    (gameId: string, username: string) => tokens[`${gameId.toUpperCase()}/${username}`],
+    [tokens]
+  );
+
+  const saveToken = useCallback(
+    (gameId: string, username: string, token: string) => {
+      setTokens((t) => ({ ...t, [`${gameId.toUpperCase()}/${username}`]: token }));
+    },
+    [setTokens]
+  );
+
+  // The URL is built again on every reconnect, so the latest token is used without a new token
+  // causing a reconnect of its own
+  const tokenRef = useRef<string | undefined>(undefined);
+  tokenRef.current = game?.id ? getToken(game.id, username) : undefined;
+  const gameIdForWs = game?.id;
+  const wsUrl = useMemo(() => {
+    if (!gameIdForWs || !role || !username) {
+      return null;
     }
-  }, [game, role, username, setWsUrl]);
+    return () => {
+      const query = tokenRef.current ? `?token=${encodeURIComponent(tokenRef.current)}` : "";
+      return `/api/stream/games/${gameIdForWs}/${role}/${username}${query}`;
+    };
+  }, [gameIdForWs, role, username]);
 
   const onWsClose = (e: CloseEvent) => {
     console.warn("WebSocket closed", e);
@@ -71,6 +91,11 @@ function App() {
   const onWsMessage = (e: WebSocketEventMap["message"]) => {
     const message: GameMessage = JSON.parse(e.data);
     switch (message.type) {
+      case "Authenticated":
+        if (game?.id) {
+          saveToken(game.id, username, message.token);
+        }
+        break;
       case "JoinGame":
         setGame((g) => {
           return { ...g, ...message.game };
@@ -136,6 +161,8 @@ function App() {
           setRole: setRole,
           gameId: gameId,
           setGameId: setGameId,
+          getToken: getToken,
+          saveToken: saveToken,
           prefs: prefs,
           setPrefs: setPrefs,
           errors: errors,
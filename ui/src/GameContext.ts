@@ -12,6 +12,8 @@ export const GameContext = createContext<{
   setRole: Dispatch<SetStateAction<PlayerRole | undefined>>;
   gameId: string;
   setGameId: Dispatch<SetStateAction<string>>;
+  getToken: (gameId: string, username: string) => string | undefined;
+  saveToken: (gameId: string, username: string, token: string) => void;
   errors: Errors;
   prefs: Preferences;
   setPrefs: Dispatch<SetStateAction<Preferences>>
@@ -26,6 +28,10 @@ export const GameContext = createContext<{
   setRole: () => {},
   gameId: "",
   setGameId: () => {},
+  // eslint-disable-next-line @typescript-eslint/no-unused-vars
+  getToken: (_gameId, _username) => undefined,
+  // eslint-disable-next-line @typescript-eslint/no-unused-vars
+  saveToken: (_gameId, _username, _token) => {},
   prefs: { hideGameId: false },
   setPrefs: () => {},
   errors: {},
@@ -64,15 +64,21 @@ export type CreateGameRequest = {
   rounds: Category[][];
 };
 
+export type CreateGameResponse = Game & {
+  hostToken: string;
+};
+
 export type GameMessage =
+  | { type: "Authenticated"; token: string }
   | { type: "JoinGame"; game: Game }
   | { type: "GameUpdate"; game: GameOverview }
   | { type: "QuestionUpdate"; question: Question }
-  | { type: "ReportError"; error: string; message: string };
+  | { type: "ReportError"; error: string; message: string; requestId?: string };
 
 export type Stats = {
   gamesCount: number;
   version: string;
+  gameIdAlphabet: string;
 };
 
 export type GameLog =
@@ -1,6 +1,6 @@
 import { Button, Modal, ModalBody, ModalHeader } from "flowbite-react";
 import { useContext, useState } from "react";
-import { CreateGameRequest, Game } from "../../Models";
+import { CreateGameRequest, CreateGameResponse } from "../../Models";
 import { GameContext } from "../../GameContext";
 import LabeledTextInput from "../../LabeledTextInput";
 import CreateRound from "./CreateRound";
@@ -9,7 +9,7 @@ import { HiPlus, HiOutlineSave, HiOutlineUpload, HiPlay } from "react-icons/hi";
 import useApiClient from "../../useApiClient";
 
 export default function CreateGame() {
-  const { setGame, setRole, setGameId, username, setUsername } = useContext(GameContext);
+  const { setGame, setRole, setGameId, saveToken, username, setUsername } = useContext(GameContext);
   const [loadGameModalOpen, setLoadGameModalOpen] = useState(false);
   const apiClient = useApiClient();
   const [request, setRequest] = useState<CreateGameRequest>({
@@ -39,7 +39,9 @@ export default function CreateGame() {
 
   const handleCreateGame = (e: React.FormEvent) => {
     e.preventDefault();
-    apiClient.createGame(request)?.then((res: Game) => {
+    apiClient.createGame(request)?.then((res: CreateGameResponse) => {
+      // Only the creator gets the host token, which they need to join as the Host
+      saveToken(res.id, username, res.hostToken);
       setGame(res);
       setRole("Host");
       setGameId(res.id);
@@ -5,15 +5,24 @@ import { Button, HRText } from "flowbite-react";
 import CreateGame from "./CreateGame";
 import About from "../../About";
 import useApiClient from "../../useApiClient";
-import { Game, PlayerRole } from "../../Models";
+import { Game, PlayerRole, Stats } from "../../Models";
 
 export default function CreateJoinGame() {
-  const { username, setUsername, setRole, setGame, gameId, setGameId } = useContext(GameContext);
+  const { username, setUsername, setRole, setGame, gameId, setGameId, getToken } = useContext(GameContext);
   const [type, setType] = useState<"join" | "create">("join");
   // False to begin with show that we only show an error after first interaction
   const [gameIdErrorMessage, setGameIdErrorMessage] = useState("");
+  const [stats, setStats] = useState<Stats | undefined>(undefined);
   const apiClient = useApiClient();
 
+  useEffect(() => {
+    apiClient.getStats()?.then((res: Stats | undefined) => {
+      if (res) {
+        setStats(res);
+      }
+    });
+  }, [setStats, apiClient]);
+
   useEffect(() => {
     // Clear out the error message if the Game ID is cleared
     if (!gameId) {
@@ -28,8 +37,10 @@ export default function CreateJoinGame() {
       return;
     }
 
-    if (!gameId.match(/[A-Z,a-z]{4}/)) {
-      setGameIdErrorMessage("Game IDs may only contain 4 characters A-Z");
+    // The length isn't checked, as games started before the server's settings changed keep their IDs
+    const alphabet = stats?.gameIdAlphabet.toUpperCase();
+    if (alphabet && [...gameId.toUpperCase()].some((x) => !alphabet.includes(x))) {
+      setGameIdErrorMessage(`Game IDs may only contain the characters ${alphabet}`);
       return;
     }
 
@@ -39,7 +50,7 @@ export default function CreateJoinGame() {
 
     setRole(role);
 
-    apiClient.getGame(gameId)?.then((res: Game | undefined) => {
+    apiClient.getGame(gameId, getToken(gameId, username))?.then((res: Game | undefined) => {
       setGame(res);
 
       // Use the role returned by the server as our current role
@@ -61,8 +72,6 @@ export default function CreateJoinGame() {
             name="gameId"
             type="text"
             placeholder="ABCD"
-            minLength={4}
-            maxLength={4}
             value={gameId}
             onChange={(e) => setGameId(e.target.value)}
             errorMessage={gameIdErrorMessage}
@@ -11,7 +11,7 @@ export default function Header() {
   const { game } = useContext(GameContext);
   const [modalOpen, setModalOpen] = useState(false);
   const [hintOpen, setHintOpen] = useState(false);
-  const [stats, setStats] = useState<Stats>({ gamesCount: 0, version: "" });
+  const [stats, setStats] = useState<Stats>({ gamesCount: 0, version: "", gameIdAlphabet: "" });
   const apiClient = useApiClient();
 
   useEffect(() => {
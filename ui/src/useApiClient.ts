@@ -88,9 +88,10 @@ export default function useApiClient() {
         });
       },
     getGame:
-      (gameId: string) => {
+      (gameId: string, token?: string) => {
         return execute(async () => {
-          const res = await fetch(`/api/games/${gameId}`);
+          const query = token ? `?token=${encodeURIComponent(token)}` : "";
+          const res = await fetch(`/api/games/${gameId}${query}`);
           if (res.status >= 500) throw Error(`Game could not be found`);
           return await res.json();
         });